@@ -0,0 +1,215 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use time::OffsetDateTime;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::cli::CONFIG_FILE;
+use crate::util::partial_download_marker;
+
+/// Files that are already compressed are stored as-is
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+	"mp4", "mkv", "webm", "mp3", "m4a", "jpg", "jpeg", "png", "gif", "zip", "gz", "7z", "rar", "docx", "pptx", "xlsx",
+];
+
+/// The zip archive written during the sync (`--zip`).
+/// Entries can't be written concurrently, so the downloads take turns adding their files.
+static ARCHIVE: Lazy<Mutex<Option<Archive>>> = Lazy::new(Mutex::default);
+
+struct Archive {
+	zip: ZipWriter<BufWriter<File>>,
+	/// Output directory, the entries are named relative to it
+	root: PathBuf,
+	/// The archive is written to this path and moved to `path` when it is complete
+	tmp: PathBuf,
+	path: PathBuf,
+	/// Names of the entries written so far
+	added: HashSet<String>,
+}
+
+/// Start writing the zip archive at `zip_path`.
+/// Files written below `root` are added to it as soon as they are complete (see `add_file`).
+pub fn start(root: &Path, zip_path: &Path) -> Result<()> {
+	let mut tmp = zip_path.as_os_str().to_owned();
+	tmp.push(".tmp");
+	let tmp = PathBuf::from(tmp);
+	let file = File::create(&tmp).context("failed to create zip archive")?;
+	*ARCHIVE.lock().unwrap() = Some(Archive {
+		zip: ZipWriter::new(BufWriter::new(file)),
+		root: root.to_owned(),
+		tmp,
+		path: zip_path.to_owned(),
+		added: HashSet::new(),
+	});
+	Ok(())
+}
+
+/// Add the file to the zip archive, if one is written.
+/// If the file is written again later, the archive contains both entries (the last one is the current file).
+pub async fn add_file(path: &Path) -> Result<()> {
+	if ARCHIVE.lock().unwrap().is_none() {
+		return Ok(());
+	}
+	let path = path.to_owned();
+	tokio::task::spawn_blocking(move || match ARCHIVE.lock().unwrap().as_mut() {
+		Some(archive) => archive.add(&path),
+		None => Ok(()),
+	})
+	.await?
+	.context("failed to add file to zip archive")
+}
+
+/// Add the files in the output directory that were not written during the sync (e.g. because they did not change)
+/// and move the archive to its final path.
+pub fn finish() -> Result<()> {
+	let mut archive = match ARCHIVE.lock().unwrap().take() {
+		Some(archive) => archive,
+		None => return Ok(()),
+	};
+	// the archive may be located in the output directory
+	let exclude = [
+		fs::canonicalize(&archive.tmp)?,
+		fs::canonicalize(&archive.path).unwrap_or_default(),
+	];
+	let root = archive.root.clone();
+	archive.add_dir(&root, &exclude)?;
+	archive.zip.finish()?.flush()?;
+	fs::rename(&archive.tmp, &archive.path).context("failed to move zip archive")?;
+	Ok(())
+}
+
+impl Archive {
+	fn add(&mut self, path: &Path) -> Result<()> {
+		match self.entry_name(path) {
+			Some(name) => self.write_entry(path, name),
+			None => Ok(()),
+		}
+	}
+
+	fn add_dir(&mut self, dir: &Path, exclude: &[PathBuf]) -> Result<()> {
+		let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+		entries.sort_by_key(|x| x.file_name());
+		for entry in entries {
+			let path = entry.path();
+			if exclude.contains(&path) {
+				continue;
+			}
+			let name = match self.entry_name(&path) {
+				Some(name) => name,
+				None => continue,
+			};
+			if !self.added.contains(&name) {
+				self.write_entry(&path, name)?;
+			}
+			if entry.file_type()?.is_dir() {
+				self.add_dir(&path, exclude)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Name of the entry for the file, `None` if it is not included in the archive.
+	/// Internal files (session, state, login and config files) and incomplete downloads are not included.
+	fn entry_name(&self, path: &Path) -> Option<String> {
+		let relative_path = path.strip_prefix(&self.root).ok()?;
+		let name = path.file_name()?.to_string_lossy();
+		if name.starts_with(".ilias")
+			|| (path.parent() == Some(&self.root) && (name == CONFIG_FILE || name == "course_names.toml"))
+			|| is_partial_download_marker(path)
+			|| partial_download_marker(path).exists()
+		{
+			return None;
+		}
+		let name = relative_path
+			.components()
+			.map(|x| x.as_os_str().to_string_lossy())
+			.collect::<Vec<_>>()
+			.join("/");
+		Some(name)
+	}
+
+	fn write_entry(&mut self, path: &Path, name: String) -> Result<()> {
+		let metadata = fs::metadata(path)?;
+		let mut options = FileOptions::default().large_file(metadata.len() >= u32::MAX as u64);
+		if let Ok(modified) = metadata.modified() {
+			if let Ok(modified) = OffsetDateTime::from(modified).try_into() {
+				options = options.last_modified_time(modified);
+			}
+		}
+		if metadata.is_dir() {
+			self.zip.add_directory(name.clone(), options)?;
+		} else {
+			let extension = path
+				.extension()
+				.map(|x| x.to_string_lossy().to_ascii_lowercase())
+				.unwrap_or_default();
+			if COMPRESSED_EXTENSIONS.contains(&&*extension) {
+				options = options.compression_method(CompressionMethod::Stored);
+			}
+			self.zip.start_file(name.clone(), options)?;
+			io::copy(&mut File::open(path)?, &mut self.zip)
+				.with_context(|| format!("failed to add {} to zip archive", path.display()))?;
+		}
+		self.added.insert(name);
+		Ok(())
+	}
+}
+
+/// Whether the file is the marker of a partial download, named `.<file name>.part` (see `partial_download_marker`).
+fn is_partial_download_marker(path: &Path) -> bool {
+	let name = path.file_name().unwrap_or_default().to_string_lossy();
+	match name.strip_prefix('.').and_then(|x| x.strip_suffix(".part")) {
+		Some(file) if !file.is_empty() => path.with_file_name(file).exists(),
+		_ => false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::util::write_file_data;
+
+	#[tokio::test]
+	async fn written_and_existing_files_are_added() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path();
+		fs::write(root.join("Blatt 1.pdf"), b"Blatt 1\n").unwrap();
+		fs::write(root.join(".ilias_state.json"), b"{}").unwrap();
+		// incomplete download
+		fs::write(root.join("Video.mp4"), b"Vid").unwrap();
+		fs::write(root.join(".Video.mp4.part"), b"").unwrap();
+		// not a partial download marker
+		fs::write(root.join(".notes.part"), b"Notizen\n").unwrap();
+		let zip_path = root.join("Semester.zip");
+		start(root, &zip_path).unwrap();
+
+		fs::create_dir(root.join("Forum")).unwrap();
+		write_file_data(root.join("Forum").join("Thread.html"), &mut &b"<html>"[..])
+			.await
+			.unwrap();
+		assert!(ARCHIVE
+			.lock()
+			.unwrap()
+			.as_ref()
+			.unwrap()
+			.added
+			.contains("Forum/Thread.html"));
+		finish().unwrap();
+
+		let mut zip = zip::ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+		let mut names = zip.file_names().collect::<Vec<_>>();
+		names.sort_unstable();
+		assert_eq!(names, [".notes.part", "Blatt 1.pdf", "Forum/", "Forum/Thread.html"]);
+		let mut html = String::new();
+		io::Read::read_to_string(&mut zip.by_name("Forum/Thread.html").unwrap(), &mut html).unwrap();
+		assert_eq!(html, "<html>");
+	}
+}
@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+/// Name of the payload directory inside the bag
+pub const PAYLOAD_DIR: &str = "data";
+
+/// Write the tag files of a BagIt bag (RFC 8493) for the payload in `root/data`.
+pub fn write_bag(root: &Path) -> Result<()> {
+	let mut files = Vec::new();
+	collect_files(&root.join(PAYLOAD_DIR), &mut files).context("failed to list payload files")?;
+	files.sort();
+
+	let mut manifest = BufWriter::new(File::create(root.join("manifest-sha256.txt"))?);
+	let mut octets = 0;
+	for path in &files {
+		let (hash, len) = hash_file(path).context("failed to hash payload file")?;
+		octets += len;
+		let relative_path = path.strip_prefix(root).unwrap();
+		let relative_path = relative_path
+			.components()
+			.map(|x| x.as_os_str().to_string_lossy())
+			.collect::<Vec<_>>()
+			.join("/");
+		writeln!(
+			manifest,
+			"{}  {}",
+			hash,
+			relative_path
+				.replace('%', "%25")
+				.replace('\r', "%0D")
+				.replace('\n', "%0A")
+		)?;
+	}
+	manifest.flush()?;
+
+	fs::write(
+		root.join("bagit.txt"),
+		"BagIt-Version: 1.0\nTag-File-Character-Encoding: UTF-8\n",
+	)?;
+	fs::write(
+		root.join("bag-info.txt"),
+		format!(
+			"Bag-Software-Agent: {} {}\nPayload-Oxum: {}.{}\n",
+			env!("CARGO_PKG_NAME"),
+			env!("CARGO_PKG_VERSION"),
+			octets,
+			files.len()
+		),
+	)?;
+	Ok(())
+}
+
+/// Returns the SHA-256 hash (hex-encoded) and the size of the file.
+pub fn hash_file(path: &Path) -> io::Result<(String, u64)> {
+	let mut hasher = Sha256::new();
+	let len = io::copy(&mut File::open(path)?, &mut hasher)?;
+	Ok((format!("{:x}", hasher.finalize()), len))
+}
+
+/// Recursively lists all files in the directory.
+pub fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+	for entry in fs::read_dir(dir)? {
+		let entry = entry?;
+		if entry.file_type()?.is_dir() {
+			collect_files(&entry.path(), files)?;
+		} else {
+			files.push(entry.path());
+		}
+	}
+	Ok(())
+}
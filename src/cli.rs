@@ -1,15 +1,23 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::io::{Error, ErrorKind};
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Write};
+use std::num::ParseIntError;
 use std::path::PathBuf;
 use std::process::Command;
-use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize};
+use std::sync::Mutex;
 
 use anyhow::anyhow;
 use anyhow::{Context, Result};
-use indicatif::ProgressBar;
-use once_cell::sync::Lazy;
+use indicatif::{MultiProgress, ProgressBar};
+use once_cell::sync::{Lazy, OnceCell};
+use regex::Regex;
 use structopt::StructOpt;
+use time::{format_description::well_known::Rfc3339, Date, Month, OffsetDateTime};
 
 #[derive(Debug, Clone, StructOpt)]
 #[structopt(name = env!("CARGO_PKG_NAME"))]
@@ -18,14 +26,143 @@ pub struct Opt {
 	#[structopt(short, long)]
 	pub skip_files: bool,
 
+	/// Still download files with these extensions when using --skip-files, e.g. pdf
+	#[structopt(long, use_delimiter = true)]
+	pub keep_ext: Vec<String>,
+
+	/// Only show what would be downloaded, without writing anything to disk
+	#[structopt(long)]
+	pub dry_run: bool,
+
+	/// Print the tree of courses, folders and the objects in them instead of downloading anything
+	/// (nothing is written to disk)
+	#[structopt(long)]
+	pub list: bool,
+
+	/// Only create the directory structure of courses and folders, without downloading any content
+	#[structopt(long, alias = "only-containers")]
+	pub structure_only: bool,
+
+	/// Only sync the courses / folders with these ref_ids (their contents are always included), e.g. 1234,5678
+	#[structopt(long, use_delimiter = true)]
+	pub only_ref_id: Vec<String>,
+
+	/// Only download files that appear in the RSS feed of their course with a date after this date (YYYY-MM-DD)
+	#[structopt(long, parse(try_from_str = parse_date))]
+	pub since: Option<Date>,
+
+	/// Client ID of the ILIAS installation, used for the RSS feeds of --since (default: detected after logging in)
+	#[structopt(long)]
+	pub client_id: Option<String>,
+
+	/// Maximum directory nesting (relative to the sync root) to descend into
+	#[structopt(long)]
+	pub max_depth: Option<usize>,
+
+	/// Download all versions of files into a versions folder (not only the latest version)
+	#[structopt(long)]
+	pub all_file_versions: bool,
+
+	/// Compare downloaded files with the MD5 checksum shown by ILIAS (if available)
+	#[structopt(long)]
+	pub verify_checksums: bool,
+
+	/// Only download files with these extensions, e.g. pdf,docx
+	#[structopt(long, use_delimiter = true)]
+	pub only_ext: Vec<String>,
+
+	/// Do not download files with these extensions, e.g. mp4,zip
+	#[structopt(long, use_delimiter = true)]
+	pub skip_ext: Vec<String>,
+
+	/// Skip files larger than this size (in bytes, or with a K/M/G suffix, e.g. 500M)
+	#[structopt(long, parse(try_from_str = parse_size))]
+	pub max_file_size: Option<u64>,
+
+	/// Amount of disk space that should remain free after each download (in bytes, or with a K/M/G suffix)
+	#[structopt(long, default_value = "0", parse(try_from_str = parse_size))]
+	pub min_free_space: u64,
+
+	/// Show a desktop notification when the sync is finished
+	#[structopt(long)]
+	pub notify: bool,
+
 	/// Do not download Opencast videos
 	#[structopt(short, long)]
 	pub no_videos: bool,
 
+	/// Only download Opencast videos (courses and folders are still searched for videos)
+	#[structopt(long, conflicts_with = "no-videos")]
+	pub videos_only: bool,
+
+	/// Resolution of downloaded videos: highest, lowest or the height in pixels (e.g. 720)
+	#[structopt(long, default_value = "highest")]
+	pub video_quality: VideoQuality,
+
 	/// Download forum content
 	#[structopt(short = "t", long)]
 	pub forum: bool,
 
+	/// Download the attachments of forum threads as one zip file (<thread>_attachments.zip)
+	#[structopt(long)]
+	pub forum_attachments_zip: bool,
+
+	/// Format of saved forum posts: html or markdown
+	#[structopt(long, default_value = "html")]
+	pub forum_format: ForumFormat,
+
+	/// Download blogs
+	#[structopt(long)]
+	pub blog: bool,
+
+	/// Download the tables of data collections as CSV
+	#[structopt(long)]
+	pub data_collections: bool,
+
+	/// Download wikis
+	#[structopt(long)]
+	pub wiki: bool,
+
+	/// Download surveys (questions and results, if accessible)
+	#[structopt(long)]
+	pub survey: bool,
+
+	/// Download tests (your answers and results, if accessible)
+	#[structopt(long)]
+	pub tests: bool,
+
+	/// Download learning modules
+	#[structopt(long)]
+	pub learning_modules: bool,
+
+	/// Download images embedded in exercise feedback
+	#[structopt(long)]
+	pub download_feedback_images: bool,
+
+	/// Write the deadlines of all exercise assignments into this iCalendar (.ics) file
+	#[structopt(long, parse(from_os_str))]
+	pub deadlines_ical: Option<PathBuf>,
+
+	/// Download glossaries
+	#[structopt(long)]
+	pub glossary: bool,
+
+	/// Save each glossary term in a separate file instead of a single glossary.html
+	#[structopt(long)]
+	pub glossary_per_term: bool,
+
+	/// Download the questions of shared question pools
+	#[structopt(long)]
+	pub question_pools: bool,
+
+	/// Write a <file name>.meta.json file with the URL, version and download time next to each file
+	#[structopt(long)]
+	pub metadata_sidecars: bool,
+
+	/// Do not set the modification time of downloaded files to the time reported by the server
+	#[structopt(long)]
+	pub no_set_mtime: bool,
+
 	/// Re-download already present files
 	#[structopt(short)]
 	pub force: bool,
@@ -46,22 +183,91 @@ pub struct Opt {
 	#[structopt(long)]
 	pub save_ilias_pages: bool,
 
+	/// After syncing, write an index.html into every directory linking to its contents
+	#[structopt(long)]
+	pub generate_index: bool,
+
+	/// Compress saved HTML pages using gzip
+	#[structopt(long)]
+	pub compress_html: bool,
+
+	/// Merge folders that only contain a single folder into one directory
+	#[structopt(long)]
+	pub flatten_single_child_folders: bool,
+
+	/// Don't create directories for folders inside courses, prefix the file names with the folder path instead
+	#[structopt(long)]
+	pub flatten: bool,
+
+	/// Prefix directory names with the kind of ILIAS object, e.g. "[forum] Diskussion"
+	#[structopt(long)]
+	pub prefix_kind: bool,
+
 	/// Verbose logging
 	#[structopt(short, multiple = true, parse(from_occurrences))]
 	pub verbose: usize,
 
+	/// Append all log messages (regardless of verbosity) to this file
+	#[structopt(long, parse(from_os_str))]
+	pub log_file: Option<PathBuf>,
+
 	/// Output directory
 	#[structopt(short, long, parse(from_os_str))]
 	pub output: PathBuf,
 
+	/// Config file with default values for the options (default: <output>/kit-ilias-downloader.toml)
+	#[structopt(long, parse(from_os_str))]
+	pub config: Option<PathBuf>,
+
+	/// Log how long each object and request took, and list the slowest objects at the end
+	#[structopt(long)]
+	pub verbose_timing: bool,
+
+	/// Print groups of identical files in the output directory after downloading
+	#[structopt(long)]
+	pub report_duplicates: bool,
+
+	/// Format of saved web links: txt, url (Windows), webloc (macOS) or desktop (Linux)
+	#[structopt(long, default_value = "txt")]
+	pub weblink_format: WeblinkFormat,
+
+	/// Overwrite saved ILIAS pages: always, if-changed or never
+	#[structopt(long, default_value = "always")]
+	pub overwrite_policy: OverwritePolicy,
+
+	/// Save the output as an archive package (supported: bagit)
+	#[structopt(long)]
+	pub archive_format: Option<ArchiveFormat>,
+
+	/// Also write the synced files into this zip archive
+	/// (the downloaded files are kept, so this needs about twice the disk space)
+	#[structopt(long, parse(from_os_str))]
+	pub zip: Option<PathBuf>,
+
+	/// Permissions of created files in octal, e.g. 640 (Unix only, directories are also made searchable)
+	#[structopt(long, parse(try_from_str = parse_mode))]
+	pub chmod: Option<u32>,
+
+	/// Maximum length of file and directory names in bytes, longer names are shortened
+	#[structopt(long, default_value = "200")]
+	pub max_name_length: usize,
+
 	/// Parallel download jobs
 	#[structopt(short, long, default_value = "1")]
 	pub jobs: usize,
 
-	/// Proxy, e.g. socks5h://127.0.0.1:1080
+	/// Maximum number of concurrent requests and downloads from the same host (default: no limit besides --jobs)
+	#[structopt(long)]
+	pub per_host_jobs: Option<usize>,
+
+	/// Proxy, e.g. socks5h://127.0.0.1:1080 (default: HTTP_PROXY / HTTPS_PROXY environment variables)
 	#[structopt(short, long)]
 	pub proxy: Option<String>,
 
+	/// Hosts that are accessed without the proxy, e.g. opencast.example.com,cdn.example.com
+	#[structopt(long, use_delimiter = true)]
+	pub no_proxy: Vec<String>,
+
 	/// Use the system keyring
 	#[structopt(long)]
 	pub keyring: bool,
@@ -82,33 +288,347 @@ pub struct Opt {
 	#[structopt(long)]
 	pub sync_url: Option<String>,
 
+	/// Base URL of the ILIAS installation
+	#[structopt(long, default_value = "https://ilias.studium.kit.edu/")]
+	pub base_url: String,
+
+	/// Shibboleth identity provider used to log in
+	#[structopt(long, default_value = "https://idp.scc.kit.edu/idp/shibboleth")]
+	pub idp_url: String,
+
+	/// How to log in: shibboleth or local (ILIAS account without single sign-on)
+	#[structopt(long, default_value = "shibboleth")]
+	pub login_method: LoginMethod,
+
+	/// Follow unrecognized ILIAS shortlinks to find out what they point to (one HEAD request per link)
+	#[structopt(long)]
+	pub resolve_shortlinks: bool,
+
+	/// Download the repository node with this ref_id (e.g. shared materials in the magazine)
+	#[structopt(long)]
+	pub repo_ref_id: Option<usize>,
+
 	/// Requests per minute
 	#[structopt(long, default_value = "8")]
 	pub rate: usize,
 
+	/// Number of requests that may be sent immediately after an idle period (the average stays at --rate)
+	#[structopt(long, default_value = "1")]
+	pub rate_burst: usize,
+
+	/// Maximum random delay (in milliseconds) added between requests
+	#[structopt(long, default_value = "0")]
+	pub jitter: u64,
+
+	/// Maximum download bandwidth in bytes per second (shared by all parallel jobs)
+	#[structopt(long)]
+	pub max_bandwidth: Option<usize>,
+
 	/// Attempt to re-use session cookies
 	#[structopt(long)]
 	pub keep_session: bool,
 
+	/// Retry failed requests (connection errors, timeouts and server errors) this many times
+	#[structopt(long, default_value = "3")]
+	pub max_retries: usize,
+
+	/// Only use HTTP/1.1 (avoids retrying HTTP/2 NO_ERROR failures, but may be slightly slower)
+	#[structopt(long)]
+	pub http1_only: bool,
+
+	/// User-Agent header sent with all requests (default: KIT-ILIAS-downloader/<version>)
+	#[structopt(long)]
+	pub user_agent: Option<String>,
+
+	/// Abort requests if the server doesn't respond for this many seconds (0 = never)
+	#[structopt(long, default_value = "60")]
+	pub timeout: u64,
+
+	/// Maximum age of the saved session in minutes, older sessions are not re-used
+	#[structopt(long, default_value = "60")]
+	pub session_max_age: u64,
+
+	/// Check the session every N minutes and log in again if it expired
+	#[structopt(long)]
+	pub refresh_session: Option<u64>,
+
+	/// Save the session cookies to this file after logging in
+	#[structopt(long, parse(from_os_str))]
+	pub dump_cookies: Option<PathBuf>,
+
+	/// Load session cookies from this file (instead of logging in, if the session is still valid)
+	#[structopt(long, parse(from_os_str))]
+	pub load_cookies: Option<PathBuf>,
+
 	/// Download all courses
 	#[structopt(long)]
 	pub all: bool,
+
+	/// Do not include the version in file names, overwrite the file when it is updated
+	#[structopt(long)]
+	pub strip_version_suffix: bool,
+
+	/// Command to run after a file is downloaded (called with the file path as argument)
+	#[structopt(long)]
+	pub post_download_hook: Option<String>,
+
+	/// Abort the sync (exit code 1) if the post-download hook fails
+	#[structopt(long)]
+	pub strict: bool,
+}
+
+/// Name of the config file in the output directory
+pub const CONFIG_FILE: &str = "kit-ilias-downloader.toml";
+
+impl Opt {
+	/// Use the values of the config file for all options not specified on the command line.
+	/// Precedence: command line > config file > defaults.
+	/// `args` are the command line arguments `self` was parsed from.
+	pub fn load_config(self, args: &[OsString]) -> Result<Self> {
+		let path = self.config.clone().unwrap_or_else(|| self.output.join(CONFIG_FILE));
+		let config = match std::fs::read_to_string(&path) {
+			Ok(config) => config,
+			Err(e) if self.config.is_none() && e.kind() == ErrorKind::NotFound => return Ok(self),
+			Err(e) => return Err(e).context("failed to read config file"),
+		};
+		let config: toml::Table = toml::from_str(&config).context("failed to parse config file")?;
+
+		let matches = Opt::clap()
+			.get_matches_from_safe(args)
+			.map_err(|e| anyhow!("{}", e.message))?;
+		let mut args = args.to_vec();
+		for (key, value) in config {
+			// structopt names the arguments like their long flag
+			let name = key.replace('_', "-");
+			if matches.occurrences_of(&name) > 0 {
+				continue; // specified on the command line
+			}
+			let flag = format!("--{}", name);
+			match value {
+				toml::Value::Boolean(true) => args.push(flag.into()),
+				toml::Value::Boolean(false) => {},
+				toml::Value::String(value) => args.extend([flag.into(), value.into()]),
+				toml::Value::Array(values) => {
+					let values = values
+						.iter()
+						.map(|x| x.as_str().map(|x| x.to_owned()).unwrap_or_else(|| x.to_string()))
+						.collect::<Vec<_>>();
+					args.extend([flag.into(), values.join(",").into()]);
+				},
+				value => args.extend([flag.into(), value.to_string().into()]),
+			}
+		}
+		Opt::from_iter_safe(args)
+			.map_err(|e| anyhow!("{}", e.message))
+			.with_context(|| format!("invalid option in config file {}", path.display()))
+	}
+
+	/// Directory the ILIAS content is saved to.
+	/// With `--archive-format bagit`, this is the payload directory of the bag.
+	pub fn content_dir(&self) -> PathBuf {
+		match self.archive_format {
+			Some(ArchiveFormat::BagIt) => self.output.join(crate::bagit::PAYLOAD_DIR),
+			None => self.output.clone(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+	BagIt,
+}
+
+impl FromStr for ArchiveFormat {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"bagit" => Ok(ArchiveFormat::BagIt),
+			_ => Err(anyhow!("unknown archive format {:?}", s)),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VideoQuality {
+	Highest,
+	Lowest,
+	Height(u32),
+}
+
+impl FromStr for VideoQuality {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"highest" => Ok(VideoQuality::Highest),
+			"lowest" => Ok(VideoQuality::Lowest),
+			_ => Ok(VideoQuality::Height(
+				s.trim_end_matches('p')
+					.parse()
+					.map_err(|_| anyhow!("unknown video quality {:?}", s))?,
+			)),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WeblinkFormat {
+	Txt,
+	Url,
+	Webloc,
+	Desktop,
+}
+
+impl FromStr for WeblinkFormat {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"txt" => Ok(WeblinkFormat::Txt),
+			"url" => Ok(WeblinkFormat::Url),
+			"webloc" => Ok(WeblinkFormat::Webloc),
+			"desktop" => Ok(WeblinkFormat::Desktop),
+			_ => Err(anyhow!("unknown weblink format {:?}", s)),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoginMethod {
+	Shibboleth,
+	Local,
+}
+
+impl FromStr for LoginMethod {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"shibboleth" => Ok(LoginMethod::Shibboleth),
+			"local" => Ok(LoginMethod::Local),
+			_ => Err(anyhow!("unknown login method {:?}", s)),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForumFormat {
+	Html,
+	Markdown,
+}
+
+impl FromStr for ForumFormat {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"html" => Ok(ForumFormat::Html),
+			"markdown" | "md" => Ok(ForumFormat::Markdown),
+			_ => Err(anyhow!("unknown forum format {:?}", s)),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverwritePolicy {
+	Always,
+	IfChanged,
+	Never,
+}
+
+impl FromStr for OverwritePolicy {
+	type Err = anyhow::Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"always" => Ok(OverwritePolicy::Always),
+			"if-changed" => Ok(OverwritePolicy::IfChanged),
+			"never" => Ok(OverwritePolicy::Never),
+			_ => Err(anyhow!("unknown overwrite policy {:?}", s)),
+		}
+	}
+}
+
+fn parse_mode(mode: &str) -> Result<u32, ParseIntError> {
+	u32::from_str_radix(mode, 8)
+}
+
+fn parse_size(size: &str) -> Result<u64> {
+	let size = size.trim().to_ascii_uppercase();
+	let size = size.trim_end_matches("IB").trim_end_matches('B');
+	let (number, factor) = match size.char_indices().last() {
+		Some((i, 'K')) => (&size[..i], 1 << 10),
+		Some((i, 'M')) => (&size[..i], 1 << 20),
+		Some((i, 'G')) => (&size[..i], 1 << 30),
+		_ => (size, 1),
+	};
+	let number = number.parse::<u64>().context("invalid size, expected e.g. 500M")?;
+	Ok(number * factor)
+}
+
+fn parse_date(date: &str) -> Result<Date> {
+	let parts = date
+		.splitn(3, '-')
+		.map(|x| x.parse::<u16>().ok())
+		.collect::<Option<Vec<_>>>()
+		.filter(|x| x.len() == 3)
+		.context("invalid date, expected YYYY-MM-DD")?;
+	let month = Month::try_from(parts[1] as u8)?;
+	Ok(Date::from_calendar_date(parts[0] as i32, month, parts[2] as u8)?)
 }
 
 pub static LOG_LEVEL: AtomicUsize = AtomicUsize::new(0);
+/// File mode set using `--chmod` (0 = default permissions)
+pub static FILE_MODE: AtomicU32 = AtomicU32::new(0);
+/// Set using `--dry-run`: nothing is written to disk
+pub static DRY_RUN: AtomicBool = AtomicBool::new(false);
+/// Maximum file name length set using `--max-name-length`
+pub static MAX_NAME_LENGTH: AtomicUsize = AtomicUsize::new(200);
 pub static PROGRESS_BAR_ENABLED: AtomicBool = AtomicBool::new(false);
 pub static PROGRESS_BAR: Lazy<ProgressBar> = Lazy::new(|| ProgressBar::new(0));
+/// Contains the main progress bar and the progress bars of the running downloads
+pub static MULTI_PROGRESS: Lazy<MultiProgress> = Lazy::new(MultiProgress::new);
+
+/// Set using `--log-file`
+pub static LOG_FILE: OnceCell<Mutex<File>> = OnceCell::new();
+/// Set using `--base-url`
+pub static BASE_URL: OnceCell<String> = OnceCell::new();
+
+/// Base URL of the ILIAS installation (ending with a slash)
+pub fn ilias_url() -> &'static str {
+	BASE_URL
+		.get()
+		.map(String::as_str)
+		.unwrap_or("https://ilias.studium.kit.edu/")
+}
+static ANSI_ESCAPE: Lazy<Regex> = Lazy::new(|| Regex::new("\x1b\\[[0-9;]*m").unwrap());
+
+/// Append the line to the `--log-file` (if any), prefixed by the current time.
+pub fn write_log_file(line: &str) {
+	if let Some(file) = LOG_FILE.get() {
+		let time = OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default();
+		if let Ok(mut file) = file.lock() {
+			let _ = writeln!(file, "{} {}", time, ANSI_ESCAPE.replace_all(line, ""));
+		}
+	}
+}
 
 macro_rules! log {
 	($lvl:expr, $($t:expr),+) => {{
 		#[allow(unused_imports)]
 		use colored::Colorize as _;
 		#[allow(unused_comparisons)] // 0 <= 0
-		if $lvl <= crate::cli::LOG_LEVEL.load(std::sync::atomic::Ordering::SeqCst) {
-			if crate::cli::PROGRESS_BAR_ENABLED.load(std::sync::atomic::Ordering::SeqCst) {
-				crate::cli::PROGRESS_BAR.println(format!($($t),+));
-			} else {
-				println!($($t),+);
+		let enabled = $lvl <= crate::cli::LOG_LEVEL.load(std::sync::atomic::Ordering::SeqCst);
+		if enabled || crate::cli::LOG_FILE.get().is_some() {
+			let line = format!($($t),+);
+			crate::cli::write_log_file(&line);
+			if enabled {
+				if crate::cli::PROGRESS_BAR_ENABLED.load(std::sync::atomic::Ordering::SeqCst) {
+					crate::cli::PROGRESS_BAR.println(line);
+				} else {
+					println!("{}", line);
+				}
 			}
 		}
 	}}
@@ -128,29 +648,36 @@ macro_rules! success {
 
 macro_rules! warning {
 	($e:expr) => {{
+		crate::stats::WARNINGS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
 		log!(0, "Warning: {}", format!("{:?}", $e).bright_yellow());
 	}};
 	($msg:expr, $e:expr) => {{
+		crate::stats::WARNINGS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
 		log!(0, "Warning: {}", format!("{} {:?}", $msg, $e).bright_yellow());
 	}};
 	($msg1:expr, $msg2:expr, $e:expr) => {{
+		crate::stats::WARNINGS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
 		log!(0, "Warning: {}", format!("{} {} {:?}", $msg1, $msg2, $e).bright_yellow());
 	}};
 	(format => $($e:expr),+) => {{
+		crate::stats::WARNINGS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
 		log!(0, "Warning: {}", format!($($e),+).bright_yellow());
 	}};
 	($lvl:expr; $($e:expr),+) => {{
+		crate::stats::WARNINGS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
 		log!($lvl, "Warning: {}", format!($($e),+).bright_yellow());
 	}};
 }
 
 macro_rules! error {
-	($($prefix:expr),+; $e:expr) => {
+	($($prefix:expr),+; $e:expr) => {{
+		crate::stats::ERRORS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
 		log!(0, "{}: {}", format!($($prefix),+), format!("{:?}", $e).bright_red());
-	};
-	($e:expr) => {
+	}};
+	($e:expr) => {{
+		crate::stats::ERRORS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
 		log!(0, "Error: {}", format!("{:?}", $e).bright_red());
-	};
+	}};
 }
 
 pub fn ask_user_pass(opt: &Opt) -> Result<(String, String)> {
@@ -213,3 +740,43 @@ pub fn ask_user_pass(opt: &Opt) -> Result<(String, String)> {
 	}
 	Ok((user, pass))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn load(config: &str, args: &[&str]) -> Opt {
+		let dir = tempfile::tempdir().unwrap();
+		std::fs::write(dir.path().join(CONFIG_FILE), config).unwrap();
+		let mut all_args = vec![OsString::from("ilias"), "-o".into(), dir.path().into()];
+		all_args.extend(args.iter().map(OsString::from));
+		Opt::from_iter(&all_args).load_config(&all_args).unwrap()
+	}
+
+	#[test]
+	fn config_precedence() {
+		let config = "jobs = 4\nper_host_jobs = 2\nno-videos = true\nskip-ext = [\"mp4\", \"zip\"]\n";
+		let opt = load(config, &["--jobs", "8", "--per-host-jobs", "3"]);
+		// command line > config file
+		assert_eq!(opt.jobs, 8);
+		assert_eq!(opt.per_host_jobs, Some(3));
+		// config file > defaults
+		assert!(opt.no_videos);
+		assert_eq!(opt.skip_ext, ["mp4", "zip"]);
+		// defaults
+		assert_eq!(opt.max_depth, None);
+		assert!(!opt.skip_files);
+
+		let opt = load(config, &[]);
+		assert_eq!(opt.jobs, 4);
+		assert_eq!(opt.per_host_jobs, Some(2));
+	}
+
+	#[test]
+	fn config_invalid_option() {
+		let dir = tempfile::tempdir().unwrap();
+		std::fs::write(dir.path().join(CONFIG_FILE), "no-such-option = 1\n").unwrap();
+		let args = vec![OsString::from("ilias"), "-o".into(), dir.path().into()];
+		assert!(Opt::from_iter(&args).load_config(&args).is_err());
+	}
+}
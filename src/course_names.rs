@@ -0,0 +1,53 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{borrow::Cow, collections::HashMap, path::Path};
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+/// Mapping of course names to folder names (`course_names.toml`).
+///
+/// Keys are matched exactly first. Keys containing regex syntax are then tried
+/// as patterns in the order of the file, the first matching pattern wins:
+/// ```toml
+/// "^\\[SS\\d+\\] (.*)" = "$1"
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CourseNames {
+	exact: HashMap<String, String>,
+	patterns: Vec<(Regex, String)>,
+}
+
+impl CourseNames {
+	pub fn load(path: &Path) -> Result<Self> {
+		let table: toml::Table = toml::from_str(&std::fs::read_to_string(path).context("accessing course_names.toml")?)
+			.context("processing course_names.toml")?;
+		let mut names = CourseNames::default();
+		for (key, value) in table {
+			let value = value
+				.as_str()
+				.with_context(|| format!("course_names.toml: value of {:?} is not a string", key))?
+				.to_owned();
+			// plain names are only matched exactly
+			if regex::escape(&key) != key {
+				match Regex::new(&key) {
+					Ok(regex) => names.patterns.push((regex, value.clone())),
+					Err(e) => log!(1, "course_names.toml: {:?} is not a valid pattern: {}", key, e),
+				}
+			}
+			names.exact.insert(key, value);
+		}
+		Ok(names)
+	}
+
+	/// Returns the folder name configured for this course, if any.
+	pub fn get(&self, name: &str) -> Option<Cow<'_, str>> {
+		if let Some(value) = self.exact.get(name) {
+			return Some(Cow::Borrowed(value));
+		}
+		self.patterns
+			.iter()
+			.find(|(regex, _)| regex.is_match(name))
+			.map(|(regex, value)| Cow::Owned(regex.replace(name, value.as_str()).into_owned()))
+	}
+}
@@ -0,0 +1,71 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::bagit::{collect_files, hash_file};
+
+/// Print groups of identical files in the output directory (`--report-duplicates`).
+/// Files starting with a dot (session, ignore files, ..) are not considered.
+pub fn report(root: &Path) -> Result<()> {
+	let mut files = Vec::new();
+	collect_files(root, &mut files).context("failed to list files")?;
+
+	// only files of the same size can be identical
+	let mut by_size = HashMap::<u64, Vec<PathBuf>>::new();
+	for path in files {
+		if path
+			.file_name()
+			.map(|x| x.to_string_lossy().starts_with('.'))
+			.unwrap_or(true)
+		{
+			continue;
+		}
+		let len = fs::metadata(&path)?.len();
+		if len > 0 {
+			by_size.entry(len).or_default().push(path);
+		}
+	}
+	let mut groups = Vec::new();
+	for (len, paths) in by_size.into_iter().filter(|(_, paths)| paths.len() > 1) {
+		let mut by_hash = HashMap::<String, Vec<PathBuf>>::new();
+		for path in paths {
+			let (hash, _) = hash_file(&path).with_context(|| format!("failed to hash {}", path.display()))?;
+			by_hash.entry(hash).or_default().push(path);
+		}
+		groups.extend(
+			by_hash
+				.into_values()
+				.filter(|paths| paths.len() > 1)
+				.map(|paths| (len, paths)),
+		);
+	}
+	groups.sort_by_key(|(len, paths)| std::cmp::Reverse(len * paths.len() as u64));
+
+	let mut reclaimable = 0;
+	for (len, mut paths) in groups {
+		paths.sort();
+		reclaimable += len * (paths.len() as u64 - 1);
+		log!(0, "{} identical files ({}):", paths.len(), format_size(len));
+		for path in paths {
+			log!(0, "  {}", path.strip_prefix(root).unwrap_or(&path).display());
+		}
+	}
+	log!(0, "Reclaimable space: {}", format_size(reclaimable));
+	Ok(())
+}
+
+pub fn format_size(bytes: u64) -> String {
+	if bytes < 1024 {
+		format!("{} B", bytes)
+	} else if bytes < 1024 * 1024 {
+		format!("{:.1} KiB", bytes as f64 / 1024.0)
+	} else if bytes < 1024 * 1024 * 1024 {
+		format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+	} else {
+		format!("{:.1} GiB", bytes as f64 / (1024.0 * 1024.0 * 1024.0))
+	}
+}
@@ -0,0 +1,74 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+/// A calendar entry at a single point in time
+#[derive(Clone, Debug)]
+pub struct Event {
+	pub summary: String,
+	pub description: String,
+	/// Local time (written as "floating" time, i.e. in the timezone of the calendar)
+	pub time: PrimitiveDateTime,
+}
+
+/// Write the events into an iCalendar file (RFC 5545).
+pub fn write_calendar(path: &Path, events: &[Event]) -> Result<()> {
+	let now = OffsetDateTime::now_utc();
+	let stamp = format!("{}Z", format_time(PrimitiveDateTime::new(now.date(), now.time())));
+	let mut ics = String::new();
+	push_line(&mut ics, "BEGIN:VCALENDAR");
+	push_line(&mut ics, "VERSION:2.0");
+	let product = concat!(env!("CARGO_PKG_NAME"), "//", env!("CARGO_PKG_VERSION"));
+	push_line(&mut ics, &format!("PRODID:-//{}//EN", product));
+	for event in events {
+		// stable across runs, so calendar applications update the existing entries
+		let uid = Sha256::digest(format!("{}\n{}", event.summary, event.description).as_bytes());
+		push_line(&mut ics, "BEGIN:VEVENT");
+		push_line(&mut ics, &format!("UID:{:x}@{}", uid, env!("CARGO_PKG_NAME")));
+		push_line(&mut ics, &format!("DTSTAMP:{}", stamp));
+		push_line(&mut ics, &format!("DTSTART:{}", format_time(event.time)));
+		push_line(&mut ics, &format!("DTEND:{}", format_time(event.time)));
+		push_line(&mut ics, &format!("SUMMARY:{}", escape_text(&event.summary)));
+		push_line(&mut ics, &format!("DESCRIPTION:{}", escape_text(&event.description)));
+		push_line(&mut ics, "END:VEVENT");
+	}
+	push_line(&mut ics, "END:VCALENDAR");
+	std::fs::write(path, ics).context("failed to write calendar file")
+}
+
+fn format_time(time: PrimitiveDateTime) -> String {
+	format!(
+		"{:04}{:02}{:02}T{:02}{:02}{:02}",
+		time.year(),
+		time.month() as u8,
+		time.day(),
+		time.hour(),
+		time.minute(),
+		time.second()
+	)
+}
+
+fn escape_text(text: &str) -> String {
+	text.replace('\\', "\\\\")
+		.replace(';', "\\;")
+		.replace(',', "\\,")
+		.replace('\n', "\\n")
+}
+
+/// Append the content line, folded after 75 bytes.
+fn push_line(ics: &mut String, line: &str) {
+	let mut len = 0;
+	for c in line.chars() {
+		if len + c.len_utf8() > 75 {
+			ics.push_str("\r\n ");
+			len = 1;
+		}
+		ics.push(c);
+		len += c.len_utf8();
+	}
+	ics.push_str("\r\n");
+}
@@ -1,28 +1,65 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::{collections::HashMap, error::Error as _, io::Write, sync::Arc};
+use std::{
+	borrow::Cow,
+	collections::{HashMap, HashSet},
+	convert::TryInto,
+	error::Error as _,
+	io::Write,
+	path::Path,
+	sync::{atomic::Ordering, Arc, Mutex},
+	time::{Duration, Instant, SystemTime},
+};
 
 use anyhow::{anyhow, Context, Result};
 use cookie_store::CookieStore;
+use futures::future::{BoxFuture, FutureExt};
+use futures::StreamExt;
+use md5::{Digest, Md5};
 use once_cell::sync::Lazy;
-use reqwest::{Client, IntoUrl, Proxy, Url};
+use regex::Regex;
+use reqwest::{header::HeaderMap, Client, IntoUrl, Proxy, RequestBuilder, Url};
 use reqwest_cookie_store::CookieStoreMutex;
 use scraper::{ElementRef, Html, Selector};
 use serde_json::json;
+use time::{format_description::well_known::Rfc2822, OffsetDateTime};
+use tokio::process::Command;
 
-use crate::{cli::Opt, iliasignore::IliasIgnore, queue, util::wrap_html, ILIAS_URL};
+use crate::{
+	cli::{ilias_url, LoginMethod, Opt},
+	course_names::CourseNames,
+	duplicates::format_size,
+	iliasignore::IliasIgnore,
+	queue,
+	state::State,
+	stats, timing,
+	util::{
+		absolute_url, body_stream, ensure_free_space, is_partial_download, last_modified, partial_download_marker,
+		wrap_html, write_download,
+	},
+};
 
+pub mod blog;
 pub mod course;
+pub mod data_collection;
 pub mod exercise;
 pub mod file;
 pub mod folder;
 pub mod forum;
+pub mod glossary;
+pub mod mediacast;
 pub mod plugin_dispatch;
+pub mod presentation;
+pub mod question_pool;
+pub mod survey;
+pub mod test;
 pub mod thread;
 pub mod video;
 pub mod weblink;
+pub mod wiki;
 
 static LINKS: Lazy<Selector> = Lazy::new(|| Selector::parse("a").unwrap());
+static USERNAME_INPUT: Lazy<Selector> = Lazy::new(|| Selector::parse(r#"input[name="username"]"#).unwrap());
 static ALERT_DANGER: Lazy<Selector> = Lazy::new(|| Selector::parse("div.alert-danger, .il_ItemAlertProperty").unwrap());
 static IL_CONTENT_CONTAINER: Lazy<Selector> = Lazy::new(|| Selector::parse("#il_center_col").unwrap());
 static BLOCK_FAVORITES: Lazy<Selector> = Lazy::new(|| Selector::parse("#block_pditems_0").unwrap());
@@ -31,15 +68,42 @@ static CONTAINER_ITEMS: Lazy<Selector> =
 	Lazy::new(|| Selector::parse("div.il_ContainerListItem, .il-std-item").unwrap());
 static CONTAINER_ITEM_TITLE: Lazy<Selector> =
 	Lazy::new(|| Selector::parse("a.il_ContainerItemTitle, .il-item-title > a").unwrap());
+/// Elements inside item blocks that may be filled asynchronously
+static CONTAINER_BLOCK_IDS: Lazy<Selector> =
+	Lazy::new(|| Selector::parse(".il_ContainerItemsContainer[id], .il_ContainerItemsContainer [id]").unwrap());
+static TREE_NODES: Lazy<Selector> = Lazy::new(|| Selector::parse(r#"li[id^="exp_node_rep_exp_"]"#).unwrap());
+static IMAGES: Lazy<Selector> = Lazy::new(|| Selector::parse("img").unwrap());
+static TREE_ICON: Lazy<Regex> = Lazy::new(|| Regex::new(r"icon_([a-z]+)\.(?:svg|png|gif)").unwrap());
+static SCRIPTS: Lazy<Selector> = Lazy::new(|| Selector::parse("script").unwrap());
+static LAZY_BLOCK_REGEX: Lazy<Regex> =
+	Lazy::new(|| Regex::new(r#"il\.Util\.ajaxReplaceInner\(\s*['"]([^'"]+)['"]\s*,\s*['"]([^'"]+)['"]"#).unwrap());
+static FORMS: Lazy<Selector> = Lazy::new(|| Selector::parse("form").unwrap());
+static FORM_INPUTS: Lazy<Selector> = Lazy::new(|| Selector::parse("input, button[name]").unwrap());
+static GOTO_REF_ID: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?:target=|goto_[^_/]+_)[a-z]+_(\d+)").unwrap());
+static JOIN_BUTTON: Lazy<Selector> =
+	Lazy::new(|| Selector::parse(r#"a[href*="cmd=join"], input[name="cmd[join]"], button[name="cmd[join]"]"#).unwrap());
 
 pub struct ILIAS {
 	pub opt: Opt,
 	pub ignore: IliasIgnore,
 	client: Client,
 	cookies: Arc<CookieStoreMutex>,
-	pub course_names: HashMap<String, String>,
+	pub course_names: CourseNames,
+	/// Download state of previous runs
+	pub state: State,
+	/// Objects listed in the RSS feeds of the courses (`--since`)
+	recent_objects: Mutex<HashSet<String>>,
+	/// Content of the folders found in the content trees of the courses (`--content-tree`)
+	tree_children: Mutex<HashMap<String, Vec<Object>>>,
+	/// Username and password, if known (required to log in again)
+	credentials: Option<(String, String)>,
+	/// Number and time of automatic logins after the session expired
+	relogins: tokio::sync::Mutex<(usize, Option<Instant>)>,
 }
 
+/// Give up if the session keeps expiring
+const MAX_RELOGINS: usize = 3;
+
 /// Returns true if the error is caused by:
 /// "http2 error: protocol error: not a result of an error"
 fn error_is_http2(error: &reqwest::Error) -> bool {
@@ -52,24 +116,109 @@ fn error_is_http2(error: &reqwest::Error) -> bool {
 		.unwrap_or(false)
 }
 
+/// Connection errors and timeouts are usually temporary.
+fn error_is_transient(error: &reqwest::Error) -> bool {
+	error.is_connect() || error.is_timeout()
+}
+
+/// Exponential backoff: 1s, 2s, 4s, ..
+fn retry_delay(retry: usize) -> Duration {
+	Duration::from_secs(1 << (retry - 1).min(6))
+}
+
+/// First byte of a partial response, from its `Content-Range: bytes <start>-<end>/<size>` header.
+fn content_range_start(resp: &reqwest::Response) -> Option<u64> {
+	let value = resp.headers().get(reqwest::header::CONTENT_RANGE)?.to_str().ok()?;
+	value.strip_prefix("bytes ")?.split('-').next()?.trim().parse().ok()
+}
+
+/// Longest delay requested by a server that is respected (longer ones are shortened)
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// Delay requested by the `Retry-After` header, given in seconds or as a HTTP date.
+/// At most `MAX_RETRY_AFTER`.
+fn retry_after(resp: &reqwest::Response) -> Option<Duration> {
+	let value = resp.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+	let delay = if let Ok(seconds) = value.parse() {
+		Duration::from_secs(seconds)
+	} else {
+		let date = OffsetDateTime::parse(value, &Rfc2822).ok()?;
+		// dates in the past mean: retry now
+		(date - OffsetDateTime::now_utc()).try_into().unwrap_or_default()
+	};
+	Some(delay.min(MAX_RETRY_AFTER))
+}
+
+const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Build the HTTP client used for all requests.
+/// Unless a proxy is specified, the HTTP_PROXY, HTTPS_PROXY, ALL_PROXY and NO_PROXY environment variables are respected.
+fn build_client(opt: &Opt, cookies: &Arc<CookieStoreMutex>) -> Result<Client> {
+	let user_agent = opt.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+	let mut builder = Client::builder()
+		.cookie_provider(Arc::clone(cookies))
+		.user_agent(user_agent);
+	if let Some(proxy) = opt.proxy.as_ref() {
+		// disables the proxies configured using environment variables
+		let proxy = Url::parse(proxy).context("invalid proxy URL")?;
+		let no_proxy = opt.no_proxy.clone();
+		builder = builder.proxy(Proxy::custom(move |url| {
+			let host = url.host_str().unwrap_or_default();
+			let bypass = no_proxy
+				.iter()
+				.any(|x| host == x || host.strip_suffix(x.as_str()).is_some_and(|x| x.ends_with('.')));
+			if bypass {
+				None
+			} else {
+				Some(proxy.clone())
+			}
+		}));
+	}
+	if opt.http1_only {
+		builder = builder.http1_only();
+	}
+	if opt.timeout > 0 {
+		// only limit the time between two reads, large downloads may take much longer in total
+		let timeout = Duration::from_secs(opt.timeout);
+		builder = builder.connect_timeout(timeout).read_timeout(timeout);
+	}
+	Ok(builder.build()?)
+}
+
+/// Extracts the file name from a `Content-Disposition` header value.
+fn content_disposition_file_name(header: &str) -> Option<String> {
+	let mut name = None;
+	for param in header.split(';').map(str::trim) {
+		if let Some(value) = param.strip_prefix("filename*=") {
+			// RFC 5987 encoding: charset'language'percent-encoded-value
+			let value = value.splitn(3, '\'').nth(2)?;
+			let mut bytes = Vec::new();
+			let mut chars = value.bytes();
+			while let Some(c) = chars.next() {
+				if c == b'%' {
+					let hex = [chars.next()?, chars.next()?];
+					bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+				} else {
+					bytes.push(c);
+				}
+			}
+			return String::from_utf8(bytes).ok();
+		} else if let Some(value) = param.strip_prefix("filename=") {
+			name = Some(value.trim_matches('"').to_owned());
+		}
+	}
+	name
+}
+
 impl ILIAS {
-	// TODO: de-duplicate the logic below
 	pub async fn with_session(
 		opt: Opt,
 		session: Arc<CookieStoreMutex>,
 		ignore: IliasIgnore,
-		course_names: HashMap<String, String>,
+		course_names: CourseNames,
 	) -> Result<Self> {
-		let mut builder = Client::builder()
-			.cookie_provider(Arc::clone(&session))
-			.user_agent(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")));
-		if let Some(proxy) = opt.proxy.as_ref() {
-			let proxy = Proxy::all(proxy)?;
-			builder = builder.proxy(proxy);
-		}
-		let client = builder
-			// timeout is infinite by default
-			.build()?;
+		let client = build_client(&opt, &session)?;
+		let state = State::load(&opt.output)?;
 		info!("Re-using previous session cookies..");
 		Ok(ILIAS {
 			opt,
@@ -77,6 +226,11 @@ impl ILIAS {
 			client,
 			cookies: session,
 			course_names,
+			state,
+			recent_objects: Mutex::default(),
+			tree_children: Mutex::default(),
+			credentials: None,
+			relogins: Default::default(),
 		})
 	}
 
@@ -85,35 +239,107 @@ impl ILIAS {
 		user: &str,
 		pass: &str,
 		ignore: IliasIgnore,
-		course_names: HashMap<String, String>,
+		course_names: CourseNames,
 	) -> Result<Self> {
 		let cookie_store = CookieStore::default();
 		let cookie_store = reqwest_cookie_store::CookieStoreMutex::new(cookie_store);
 		let cookie_store = std::sync::Arc::new(cookie_store);
-		let mut builder = Client::builder()
-			.cookie_provider(Arc::clone(&cookie_store))
-			.user_agent(concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")));
-		if let Some(proxy) = opt.proxy.as_ref() {
-			let proxy = Proxy::all(proxy)?;
-			builder = builder.proxy(proxy);
-		}
-		let client = builder
-			// timeout is infinite by default
-			.build()?;
+		let client = build_client(&opt, &cookie_store)?;
+		let state = State::load(&opt.output)?;
 		let this = ILIAS {
 			opt,
 			ignore,
 			client,
 			cookies: cookie_store,
 			course_names,
+			state,
+			recent_objects: Mutex::default(),
+			tree_children: Mutex::default(),
+			credentials: Some((user.to_owned(), pass.to_owned())),
+			relogins: Default::default(),
+		};
+		this.authenticate(user, pass).await?;
+		Ok(this)
+	}
+
+	/// Log in again using the credentials provided at startup.
+	pub async fn relogin(&self) -> Result<()> {
+		let (user, pass) = self
+			.credentials
+			.as_ref()
+			.context("can't log in again: session was restored from cookies")?;
+		self.authenticate(user, pass).await
+	}
+
+	async fn authenticate(&self, user: &str, pass: &str) -> Result<()> {
+		match self.opt.login_method {
+			LoginMethod::Shibboleth => self.authenticate_shibboleth(user, pass).await,
+			LoginMethod::Local => self.authenticate_local(user, pass).await,
+		}
+	}
+
+	/// Log in using the login form of ILIAS (local accounts).
+	async fn authenticate_local(&self, user: &str, pass: &str) -> Result<()> {
+		info!("Logging into ILIAS using local account..");
+		let login_page = self
+			.client
+			.get(format!("{}login.php?cmd=force_login", ilias_url()))
+			.send()
+			.await?;
+		let url = login_page.url().clone();
+		let text = login_page.text().await?;
+		let (action, mut form) = {
+			let dom = Html::parse_document(&text);
+			let login_form = dom
+				.select(&FORMS)
+				.find(|x| x.select(&USERNAME_INPUT).next().is_some())
+				.context("no login form found")?;
+			let action = url.join(login_form.value().attr("action").unwrap_or(""))?;
+			// hidden fields (e.g. CSRF token) and the login button
+			let mut fields = Vec::new();
+			let mut submitted = false;
+			for input in login_form.select(&FORM_INPUTS) {
+				let input = input.value();
+				let name = match input.attr("name") {
+					Some(name) => name.to_owned(),
+					None => continue,
+				};
+				let default_type = if input.name() == "button" { "submit" } else { "text" };
+				match input.attr("type").unwrap_or(default_type) {
+					"hidden" => fields.push((name, input.attr("value").unwrap_or("").to_owned())),
+					"submit" if !submitted => {
+						submitted = true;
+						fields.push((name, input.attr("value").unwrap_or("").to_owned()));
+					},
+					_ => {},
+				}
+			}
+			(action, fields)
 		};
+		form.push(("username".to_owned(), user.to_owned()));
+		form.push(("password".to_owned(), pass.to_owned()));
+		self.client.post(action).form(&form).send().await?;
+		// the dashboard is only shown to logged in users
+		let dashboard = self
+			.client
+			.get(format!("{}ilias.php?baseClass=ilDashboardGUI&cmd=show", ilias_url()))
+			.send()
+			.await?;
+		if ILIAS::is_login_redirect(&dashboard) {
+			return Err(anyhow!("login failed, incorrect password?"));
+		}
+		success!("Logged in!");
+		Ok(())
+	}
+
+	async fn authenticate_shibboleth(&self, user: &str, pass: &str) -> Result<()> {
 		info!("Logging into ILIAS using KIT account..");
-		let session_establishment = this
+		let session_establishment = self
 			.client
-			.post("https://ilias.studium.kit.edu/Shibboleth.sso/Login")
+			.post(format!("{}Shibboleth.sso/Login", ilias_url()))
 			.form(&json!({
 				"sendLogin": "1",
-				"idp_selection": "https://idp.scc.kit.edu/idp/shibboleth",
+				"idp_selection": self.opt.idp_url,
 				"target": "/shib_login.php?target=",
 				"home_organization_selection": "Mit KIT-Account anmelden"
 			}))
@@ -121,16 +347,19 @@ impl ILIAS {
 			.await?;
 		let url = session_establishment.url().clone();
 		let text = session_establishment.text().await?;
-		let dom_sso = Html::parse_document(text.as_str());
-		let csrf_token = dom_sso
-			.select(&Selector::parse(r#"input[name="csrf_token"]"#).unwrap())
-			.next()
-			.context("no CSRF token found")?
-			.value()
-			.attr("value")
-			.context("no CSRF token value")?;
+		let csrf_token = {
+			let dom_sso = Html::parse_document(text.as_str());
+			dom_sso
+				.select(&Selector::parse(r#"input[name="csrf_token"]"#).unwrap())
+				.next()
+				.context("no CSRF token found")?
+				.value()
+				.attr("value")
+				.context("no CSRF token value")?
+				.to_owned()
+		};
 		info!("Logging into Shibboleth..");
-		let login_response = this
+		let login_response = self
 			.client
 			.post(url)
 			.form(&json!({
@@ -143,97 +372,521 @@ impl ILIAS {
 			.await?
 			.text()
 			.await?;
-		let dom = Html::parse_document(&login_response);
-		let saml = Selector::parse(r#"input[name="SAMLResponse"]"#).unwrap();
-		let saml = dom
-			.select(&saml)
-			.next()
-			.context("no SAML response, incorrect password?")?;
-		let relay_state = Selector::parse(r#"input[name="RelayState"]"#).unwrap();
-		let relay_state = dom.select(&relay_state).next().context("no relay state")?;
-		info!("Logging into ILIAS..");
-		this.client
-			.post("https://ilias.studium.kit.edu/Shibboleth.sso/SAML2/POST")
-			.form(&json!({
+		let form = {
+			let dom = Html::parse_document(&login_response);
+			let saml = Selector::parse(r#"input[name="SAMLResponse"]"#).unwrap();
+			let saml = dom
+				.select(&saml)
+				.next()
+				.context("no SAML response, incorrect password?")?;
+			let relay_state = Selector::parse(r#"input[name="RelayState"]"#).unwrap();
+			let relay_state = dom.select(&relay_state).next().context("no relay state")?;
+			json!({
 				"SAMLResponse": saml.value().attr("value").context("no SAML value")?,
 				"RelayState": relay_state.value().attr("value").context("no RelayState value")?
-			}))
+			})
+		};
+		info!("Logging into ILIAS..");
+		self.client
+			.post(format!("{}Shibboleth.sso/SAML2/POST", ilias_url()))
+			.form(&form)
 			.send()
 			.await?;
 		success!("Logged in!");
-		Ok(this)
+		Ok(())
 	}
 
 	pub async fn save_session(&self) -> Result<()> {
-		let session_path = self.opt.output.join(".iliassession");
-		let mut writer = std::fs::File::create(session_path)
-			.map(std::io::BufWriter::new)
-			.unwrap();
-		let store = self.cookies.lock().map_err(|x| anyhow!("{}", x))?;
-		// save all cookies, including session cookies
-		for cookie in store.iter_unexpired().map(serde_json::to_string) {
-			writeln!(writer, "{}", cookie?)?;
+		self.save_cookies(&self.opt.output.join(".iliassession"))
+	}
+
+	/// Save all cookies as JSON (one cookie per line).
+	/// The file is replaced atomically, so an interrupted write does not corrupt it.
+	pub fn save_cookies(&self, path: &Path) -> Result<()> {
+		let dir = match path.parent() {
+			Some(dir) if !dir.as_os_str().is_empty() => dir,
+			_ => Path::new("."),
+		};
+		let file = tempfile::NamedTempFile::new_in(dir).context("failed to create cookie file")?;
+		let mut writer = std::io::BufWriter::new(file);
+		{
+			let store = self.cookies.lock().map_err(|x| anyhow!("{}", x))?;
+			// save all cookies, including session cookies
+			for cookie in store.iter_unexpired().map(serde_json::to_string) {
+				writeln!(writer, "{}", cookie?)?;
+			}
 		}
-		writer.flush()?;
+		let file = writer.into_inner().context("failed to write cookie file")?;
+		file.as_file().sync_all().context("failed to write cookie file")?;
+		file.persist(path).context("failed to replace cookie file")?;
 		Ok(())
 	}
 
 	pub async fn download(&self, url: &str) -> Result<reqwest::Response> {
+		self.download_range(url, 0).await
+	}
+
+	/// Download the URL, starting at the specified byte offset (using a `Range` header).
+	/// The server may ignore the range and send the full content instead.
+	pub async fn download_range(&self, url: &str, offset: u64) -> Result<reqwest::Response> {
+		let mut headers = HeaderMap::new();
+		if offset > 0 {
+			headers.insert(reqwest::header::RANGE, format!("bytes={}-", offset).parse()?);
+		}
+		self.download_with_headers(url, headers).await
+	}
+
+	async fn download_with_headers(&self, url: &str, headers: HeaderMap) -> Result<reqwest::Response> {
 		queue::get_request_ticket().await;
 		log!(2, "Downloading {}", url);
+		// the host may be specified without scheme
+		let host = ilias_url().split_once("://").map(|x| x.1).unwrap_or_default();
 		let url = if url.starts_with("http://") || url.starts_with("https://") {
 			url.to_owned()
-		} else if url.starts_with("ilias.studium.kit.edu") {
-			format!("https://{}", url)
+		} else if let Some(path) = url.strip_prefix(host) {
+			format!("{}{}", ilias_url(), path)
 		} else {
-			format!("{}{}", ILIAS_URL, url)
+			format!("{}{}", ilias_url(), url)
 		};
-		for attempt in 1..10 {
-			let result = self.client.get(url.clone()).send().await;
-			match result {
-				Ok(x) => return Ok(x),
-				Err(e) if attempt <= 3 && error_is_http2(&e) => {
-					warning!(1; "encountered HTTP/2 NO_ERROR, retrying download..");
-					continue;
-				},
-				Err(e) => return Err(e.into()),
-			}
-		}
-		unreachable!()
+		let request = self.client.get(url).headers(headers);
+		Ok(self.send_with_retries(request, "download").await?)
 	}
 
 	pub async fn head<U: IntoUrl>(&self, url: U) -> Result<reqwest::Response, reqwest::Error> {
 		queue::get_request_ticket().await;
-		let url = url.into_url()?;
-		for attempt in 1..10 {
-			let result = self.client.head(url.clone()).send().await;
+		self.send_with_retries(self.client.head(url), "HEAD request").await
+	}
+
+	/// Send the request, holding a ticket of its host while waiting for the response.
+	/// The ticket is stored in the response, so it is also held while streaming the body (see `body_stream`).
+	/// Rate limited requests, server errors and connection errors are retried (up to `--max-retries` times).
+	async fn send_with_retries(&self, request: RequestBuilder, what: &str) -> reqwest::Result<reqwest::Response> {
+		let request = request.build()?;
+		let host = request.url().host_str().unwrap_or_default().to_owned();
+		let start = Instant::now();
+		let mut retries = 0;
+		for attempt in 1.. {
+			// only requests with streaming bodies can't be cloned, these are never sent
+			let attempt_request = request.try_clone().expect("request body can't be cloned");
+			let permit = queue::get_host_ticket(&host).await;
+			let result = self.client.execute(attempt_request).await.map(|mut x| {
+				if let Some(permit) = permit {
+					x.extensions_mut().insert(queue::HostTicket::from(permit));
+				}
+				x
+			});
 			match result {
-				Ok(x) => return Ok(x),
+				// rate limited
+				Ok(x) if x.status() == reqwest::StatusCode::TOO_MANY_REQUESTS && retries < self.opt.max_retries => {
+					retries += 1;
+					let delay = retry_after(&x).unwrap_or_else(|| retry_delay(retries));
+					drop(x);
+					warning!(format => "{} rate limited, retrying in {}s..", what, delay.as_secs());
+					tokio::time::sleep(delay).await;
+				},
+				// the server is overloaded or restarting
+				Ok(x) if x.status().is_server_error() && retries < self.opt.max_retries => {
+					retries += 1;
+					let delay = retry_delay(retries);
+					warning!(format => "{} failed (HTTP {}), retrying in {}s..", what, x.status().as_u16(), delay.as_secs());
+					drop(x);
+					tokio::time::sleep(delay).await;
+				},
+				Ok(x) => {
+					if self.opt.verbose_timing {
+						timing::record_request(start, request.method().as_str(), request.url().as_str());
+					}
+					return Ok(x);
+				},
 				Err(e) if attempt <= 3 && error_is_http2(&e) => {
-					warning!(1; "encountered HTTP/2 NO_ERROR, retrying HEAD request..");
+					warning!(1; "encountered HTTP/2 NO_ERROR, retrying {}..", what);
 					continue;
 				},
+				Err(e) if retries < self.opt.max_retries && error_is_transient(&e) => {
+					retries += 1;
+					let delay = retry_delay(retries);
+					warning!(format => "{} failed ({}), retrying in {}s..", what, e, delay.as_secs());
+					tokio::time::sleep(delay).await;
+				},
 				Err(e) => return Err(e),
 			}
 		}
 		unreachable!()
 	}
 
-	pub fn is_error_response(html: &Html) -> bool {
-		html.select(&ALERT_DANGER).next().is_some()
-	}
-
-	pub async fn get_html(&self, url: &str) -> Result<Html> {
-		let resp = self.download(url).await?;
-		if resp
-			.url()
+	/// Returns true if the response was redirected to the login page,
+	/// i.e. the session expired.
+	fn is_login_redirect(resp: &reqwest::Response) -> bool {
+		let url = resp.url();
+		if url
 			.query()
 			.map(|x| x.contains("reloadpublic=1") || x.contains("cmd=force_login"))
 			.unwrap_or(false)
 		{
+			return true;
+		}
+		ILIAS::is_html(resp) && (url.path().ends_with("/login.php") || url.path().starts_with("/Shibboleth.sso/"))
+	}
+
+	fn is_html(resp: &reqwest::Response) -> bool {
+		resp.headers()
+			.get(reqwest::header::CONTENT_TYPE)
+			.and_then(|x| x.to_str().ok())
+			.map(|x| x.starts_with("text/html"))
+			.unwrap_or(false)
+	}
+
+	/// Some files can only be downloaded after accepting their copyright / license terms.
+	/// If the response is such a confirmation page, the form is submitted and the response
+	/// of the actual download is returned. Other responses are returned unchanged.
+	async fn accept_download_confirmation(&self, resp: reqwest::Response, path: &Path) -> Result<reqwest::Response> {
+		let html_file = path
+			.extension()
+			.map(|x| x.eq_ignore_ascii_case("html") || x.eq_ignore_ascii_case("htm"))
+			.unwrap_or(false);
+		if html_file || !ILIAS::is_html(&resp) {
+			return Ok(resp);
+		}
+		let page_url = resp.url().clone();
+		let (status, headers) = (resp.status(), resp.headers().clone());
+		let body = resp.bytes().await?;
+		let (action, form) = match download_confirmation_form(&String::from_utf8_lossy(&body), &page_url)? {
+			Some(form) => form,
+			None => {
+				// not a confirmation page, but the file itself
+				let mut page = http::Response::new(body);
+				*page.status_mut() = status;
+				*page.headers_mut() = headers;
+				return Ok(page.into());
+			},
+		};
+		log!(1, "Accepting download terms of {}", page_url);
+		queue::get_request_ticket().await;
+		let request = self.client.post(action).form(&form);
+		let resp = self.send_with_retries(request, "POST request").await?;
+		if ILIAS::is_html(&resp) {
+			return Err(anyhow!("could not accept the download terms of {}", page_url));
+		}
+		Ok(resp)
+	}
+
+	/// Download a file. Fails if the session expired instead of returning the login page.
+	pub async fn download_file(&self, url: &str) -> Result<reqwest::Response> {
+		self.download_file_with_headers(url, HeaderMap::new()).await
+	}
+
+	async fn download_file_with_headers(&self, url: &str, headers: HeaderMap) -> Result<reqwest::Response> {
+		let resp = self.download_with_headers(url, headers.clone()).await?;
+		if !ILIAS::is_login_redirect(&resp) {
+			return Ok(resp);
+		}
+		if self.credentials.is_none() {
 			return Err(anyhow!("not logged in / session expired"));
 		}
-		let text = self.download(url).await?.text().await?;
+		drop(resp); // (releases the host ticket)
+		self.relogin_after_expiry().await?;
+		let resp = self.download_with_headers(url, headers).await?;
+		if ILIAS::is_login_redirect(&resp) {
+			return Err(anyhow!("not logged in / session expired (even after logging in again)"));
+		}
+		Ok(resp)
+	}
+
+	/// Log in again after a request was redirected to the login page.
+	/// If multiple jobs notice the expired session at the same time, only the first one logs in.
+	async fn relogin_after_expiry(&self) -> Result<()> {
+		let mut relogins = self.relogins.lock().await;
+		if relogins.1.is_some_and(|x| x.elapsed() < Duration::from_secs(60)) {
+			// another job just logged in
+			return Ok(());
+		}
+		if relogins.0 >= MAX_RELOGINS {
+			return Err(anyhow!("session keeps expiring, giving up"));
+		}
+		warning!(format => "session expired, logging in again..");
+		relogins.0 += 1;
+		relogins.1 = Some(Instant::now());
+		self.relogin().await.context("failed to log in again")
+	}
+
+	/// Download a file to the specified path.
+	/// If a previous download of the file was interrupted, only the missing part is requested.
+	/// Returns the modification time reported by the server.
+	pub async fn download_to_file(&self, url: &str, path: &Path) -> Result<Option<SystemTime>> {
+		let download = self.download_to_file_if_changed(url, path, None, None).await?;
+		Ok(download.and_then(|x| x.modified))
+	}
+
+	/// Like `download_to_file`, but if an `etag` of the previous download is specified,
+	/// the file is only downloaded if it changed since then (using an `If-None-Match` header).
+	/// Files larger than `max_size` are skipped.
+	/// Returns `None` if the file was not modified or skipped.
+	pub async fn download_to_file_if_changed(
+		&self,
+		url: &str,
+		path: &Path,
+		etag: Option<&str>,
+		max_size: Option<u64>,
+	) -> Result<Option<Download>> {
+		let marker = partial_download_marker(path);
+		// an interrupted download is only resumed if the file did not change since then (If-Range)
+		let validator = tokio::fs::read_to_string(&marker).await.ok().filter(|x| !x.is_empty());
+		let offset = match validator {
+			Some(_) => tokio::fs::metadata(path).await.map(|x| x.len()).unwrap_or(0),
+			None => 0,
+		};
+		let mut headers = HeaderMap::new();
+		if let Some(validator) = validator.filter(|_| offset > 0) {
+			headers.insert(reqwest::header::RANGE, format!("bytes={}-", offset).parse()?);
+			headers.insert(reqwest::header::IF_RANGE, validator.parse()?);
+		} else if let Some(etag) = etag {
+			headers.insert(reqwest::header::IF_NONE_MATCH, etag.parse()?);
+		}
+		let mut resp = self.download_file_with_headers(url, headers).await?;
+		if resp.status() == reqwest::StatusCode::NOT_MODIFIED {
+			return Ok(None);
+		}
+		let mut offset = offset;
+		let partial = resp.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+		if offset > 0 && partial && content_range_start(&resp) != Some(offset) {
+			log!(1, "Wrong part of {} received, downloading it again", path.display());
+			offset = 0;
+			drop(resp);
+			resp = self.download_file_with_headers(url, HeaderMap::new()).await?;
+		}
+		let resp = self.accept_download_confirmation(resp, path).await?;
+		let modified = last_modified(&resp);
+		let header = |name| {
+			resp.headers()
+				.get(name)
+				.and_then(|x: &reqwest::header::HeaderValue| x.to_str().ok())
+				.map(ToOwned::to_owned)
+		};
+		let etag = header(reqwest::header::ETAG);
+		let last_modified = header(reqwest::header::LAST_MODIFIED);
+		let resume = offset > 0 && resp.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+		let expected_size = resp.content_length().map(|x| if resume { x + offset } else { x });
+		if let (Some(size), Some(max_size)) = (expected_size, max_size) {
+			if size > max_size {
+				log!(0, "Skipping large file {} ({})", path.display(), format_size(size));
+				// do not leave the beginning of the file behind
+				if offset > 0 {
+					let _ = tokio::fs::remove_file(path).await;
+					let _ = tokio::fs::remove_file(&marker).await;
+				}
+				return Ok(None);
+			}
+		}
+		if let Some(size) = resp.content_length() {
+			ensure_free_space(path, size, self.opt.min_free_space)?;
+		}
+		// (weak ETags can't be used in If-Range)
+		let validator = etag
+			.as_deref()
+			.filter(|x| !x.starts_with("W/"))
+			.or(last_modified.as_deref())
+			.unwrap_or_default();
+		// (the marker is not added to the zip archive)
+		tokio::fs::write(&marker, validator)
+			.await
+			.context("failed to write partial download marker")?;
+		// (only the existing part of resumed downloads has to be read again)
+		let mut hasher = match (self.opt.verify_checksums, resume) {
+			(true, true) => Some(md5_prefix(path, offset).await?),
+			(true, false) => Some(Md5::new()),
+			(false, _) => None,
+		};
+		let stream = body_stream(resp).inspect(|chunk| {
+			if let (Some(hasher), Ok(chunk)) = (hasher.as_mut(), chunk) {
+				hasher.update(chunk);
+			}
+		});
+		if resume {
+			log!(1, "Resuming download of {} at {} bytes", path.display(), offset);
+		}
+		write_download(path, stream, resume).await?;
+		let md5 = hasher.map(|x| format!("{:x}", x.finalize()));
+		let size = tokio::fs::metadata(path).await.ok().map(|x| x.len());
+		if let (Some(size), Some(expected_size)) = (size, expected_size) {
+			// the connection may be closed before all data was sent
+			// (the partial file is kept, the next run resumes the download)
+			if size != expected_size {
+				if size > expected_size {
+					let _ = tokio::fs::remove_file(path).await;
+					let _ = tokio::fs::remove_file(&marker).await;
+				}
+				return Err(anyhow!(
+					"incomplete download of {}: got {} of {} bytes",
+					path.display(),
+					size,
+					expected_size
+				));
+			}
+		}
+		tokio::fs::remove_file(&marker)
+			.await
+			.context("failed to remove partial download marker")?;
+		stats::DOWNLOADED.fetch_add(1, Ordering::SeqCst);
+		self.state.update(url, |entry| {
+			entry.etag = etag;
+			entry.last_modified = last_modified;
+			entry.size = size;
+		})?;
+		Ok(Some(Download { modified, md5 }))
+	}
+
+	/// Returns true if the file at `path` was not downloaded completely: the download was interrupted
+	/// or the file is smaller than the previous download / the file on the server.
+	/// (Interrupted downloads of older versions of this program are only found by their size.)
+	pub async fn is_incomplete(&self, url: &str, path: &Path) -> bool {
+		if is_partial_download(path).await {
+			return true;
+		}
+		let size = match tokio::fs::metadata(path).await {
+			Ok(meta) if meta.is_file() => meta.len(),
+			_ => return false,
+		};
+		if let Some(entry) = self.state.get(url) {
+			return entry.size.is_some_and(|x| size < x);
+		}
+		if self.opt.dry_run {
+			return false;
+		}
+		// unknown size: ask the server once, and remember the answer
+		let head = match self.head(absolute_url(url)).await {
+			Ok(head) if head.status().is_success() => head,
+			_ => return false,
+		};
+		let header = |name| head.headers().get(name).and_then(|x| x.to_str().ok());
+		let html = header(reqwest::header::CONTENT_TYPE).is_some_and(|x| x.starts_with("text/html"));
+		// (the body of HEAD responses is empty, use the header)
+		let expected_size = match header(reqwest::header::CONTENT_LENGTH).and_then(|x| x.parse().ok()) {
+			Some(expected_size) if !html => expected_size,
+			_ => return false,
+		};
+		if size < expected_size {
+			log!(
+				1,
+				"{} is incomplete ({} of {} bytes)",
+				path.display(),
+				size,
+				expected_size
+			);
+			return true;
+		}
+		if let Err(e) = self.state.update(url, |entry| entry.size = Some(expected_size)) {
+			warning!(e);
+		}
+		false
+	}
+
+	/// Run the `--post-download-hook` command (if any) on a freshly downloaded file.
+	/// Failures are only logged, unless `--strict` is active:
+	/// then the error is returned and no further jobs are started.
+	pub async fn run_post_download_hook(&self, path: &Path, kind: &str, url: &str) -> Result<()> {
+		let hook = match self.opt.post_download_hook.as_ref() {
+			Some(hook) => hook,
+			None => return Ok(()),
+		};
+		#[cfg(not(target_os = "windows"))]
+		let mut command = {
+			let mut command = Command::new("sh");
+			command.arg("-c").arg(format!("{} \"$1\"", hook)).arg("sh").arg(path);
+			command
+		};
+		#[cfg(target_os = "windows")]
+		let mut command = {
+			let mut command = Command::new("cmd");
+			command.arg("/C").arg(hook).arg(path);
+			command
+		};
+		let status = command
+			.env("ILIAS_OBJ_KIND", kind)
+			.env("ILIAS_URL", url)
+			.status()
+			.await
+			.context("failed to start post-download hook")
+			.and_then(|status| {
+				if status.success() {
+					Ok(())
+				} else {
+					Err(anyhow!("post-download hook failed with {}", status))
+				}
+			});
+		match status {
+			Err(e) if self.opt.strict => {
+				queue::abort();
+				Err(e)
+			},
+			Err(e) => {
+				error!("Post-download hook for {}", path.display(); e);
+				Ok(())
+			},
+			Ok(()) => Ok(()),
+		}
+	}
+
+	/// Remember the objects listed in the RSS feed of the course with a date after `--since`.
+	pub async fn load_recent_objects(&self, ref_id: &str) -> Result<()> {
+		let since = match self.opt.since {
+			Some(since) => since.midnight().assume_utc(),
+			None => return Ok(()),
+		};
+		let feed = self
+			.download(&format!("feed.php?client_id={}&ref_id={}", self.client_id()?, ref_id))
+			.await?
+			.text()
+			.await?;
+		let feed = roxmltree::Document::parse(&feed).context("invalid RSS feed")?;
+		let mut recent = self.recent_objects.lock().map_err(|x| anyhow!("{}", x))?;
+		for item in feed.descendants().filter(|x| x.has_tag_name("item")) {
+			let field = |name| {
+				item.children()
+					.find(|x| x.has_tag_name(name))
+					.and_then(|x| x.text())
+					.map(str::trim)
+			};
+			let date = field("pubDate").and_then(|x| OffsetDateTime::parse(x, &Rfc2822).ok());
+			if date.map(|x| x < since).unwrap_or(false) {
+				continue;
+			}
+			if let Some(ref_id) = field("link").and_then(|x| GOTO_REF_ID.captures(x).map(|x| x[1].to_owned())) {
+				recent.insert(ref_id);
+			}
+		}
+		Ok(())
+	}
+
+	/// Client ID of the ILIAS installation: `--client-id` or the `ilClientId` cookie set by ILIAS.
+	fn client_id(&self) -> Result<String> {
+		if let Some(client_id) = self.opt.client_id.as_ref() {
+			return Ok(client_id.clone());
+		}
+		let cookies = self.cookies.lock().map_err(|x| anyhow!("{}", x))?;
+		let client_id = cookies
+			.iter_any()
+			.find(|x| x.name() == "ilClientId")
+			.map(|x| x.value().to_owned());
+		client_id.context("unknown ILIAS client ID, please specify --client-id")
+	}
+
+	/// Returns true if `--since` is not active or the object is in the RSS feed of its course.
+	pub fn is_recent(&self, url: &URL) -> bool {
+		if self.opt.since.is_none() {
+			return true;
+		}
+		match (url.object_ref_id(), self.recent_objects.lock()) {
+			(Some(ref_id), Ok(recent)) => recent.contains(&ref_id),
+			_ => false,
+		}
+	}
+
+	pub fn is_error_response(html: &Html) -> bool {
+		html.select(&ALERT_DANGER).next().is_some()
+	}
+
+	pub async fn get_html(&self, url: &str) -> Result<Html> {
+		let text = self.download_file(url).await?.text().await?;
 		let html = Html::parse_document(&text);
 		if ILIAS::is_error_response(&html) {
 			Err(anyhow!("ILIAS error"))
@@ -270,43 +923,346 @@ impl ILIAS {
 
 	/// Returns subfolders, the main text in a course/folder/personal desktop and all links on the page.
 	pub async fn get_course_content(&self, url: &URL) -> Result<(Vec<Result<Object>>, Option<String>, Vec<String>)> {
-		let html = self.get_html(&url.url).await?;
+		let (mut items, main_text, links, lazy_blocks, embedded_files) = {
+			let html = self.get_html(&url.url).await?;
 
-		let main_text = if let Some(el) = html.select(&IL_CONTENT_CONTAINER).next() {
-			// if we are currently extracting the dashboard, only select the favorites
-			if let Some(el) = el.select(&BLOCK_FAVORITES).next() {
-				Some(wrap_html(&el.inner_html()))
+			let main_text = if let Some(el) = html.select(&IL_CONTENT_CONTAINER).next() {
+				// if we are currently extracting the dashboard, only select the favorites
+				if let Some(el) = el.select(&BLOCK_FAVORITES).next() {
+					Some(wrap_html(&el.inner_html()))
+				} else {
+					Some(wrap_html(&el.inner_html()))
+				}
 			} else {
-				Some(wrap_html(&el.inner_html()))
+				None
+			};
+			(
+				ILIAS::get_items(&html),
+				main_text,
+				html.select(&LINKS)
+					.flat_map(|x| x.value().attr("href").map(|x| x.to_owned()))
+					.collect(),
+				ILIAS::get_lazy_blocks(&html),
+				ILIAS::get_embedded_files(&html),
+			)
+		};
+		for block_url in lazy_blocks {
+			log!(1, "Loading item block {}", block_url);
+			let html = self.get_html_fragment(&block_url).await?;
+			items.extend(ILIAS::get_items(&html));
+		}
+		// (embedded files are not needed if files are skipped anyway)
+		for (url, text) in embedded_files
+			.into_iter()
+			.filter(|_| !self.opt.skip_files || !self.opt.keep_ext.is_empty())
+		{
+			let name = self.get_embedded_file_name(&url, text).await;
+			items.push(Ok(Object::File {
+				name,
+				url,
+				version: None,
+			}));
+		}
+		Ok((items, main_text, links))
+	}
+
+	/// Returns the files linked in the page text (`cmd=sendfile` links) with their link texts.
+	fn get_embedded_files(html: &Html) -> Vec<(URL, String)> {
+		let mut seen = HashSet::new();
+		let mut files = Vec::new();
+		for container in html.select(&IL_CONTENT_CONTAINER) {
+			for link in container.select(&LINKS) {
+				let url = match link.value().attr("href").map(URL::from_href) {
+					Some(Ok(url)) if url.is_sendfile() => url,
+					_ => continue,
+				};
+				if seen.insert(url.url.clone()) {
+					files.push((url, link.text().collect::<String>().trim().to_owned()));
+				}
 			}
-		} else {
-			None
+		}
+		files
+	}
+
+	/// Determines the file name of an embedded file.
+	/// The link text is used if it looks like a file name, otherwise the name sent by the server.
+	pub(crate) async fn get_embedded_file_name(&self, url: &URL, text: String) -> String {
+		if text.contains('.') {
+			return text;
+		}
+		if let Some(name) = self.state.get(&url.url).and_then(|x| x.name) {
+			return name;
+		}
+		let from_header = match self.head(&url.url).await {
+			Ok(resp) => resp
+				.headers()
+				.get(reqwest::header::CONTENT_DISPOSITION)
+				.and_then(|x| x.to_str().ok())
+				.and_then(content_disposition_file_name),
+			Err(e) => {
+				warning!("could not determine name of embedded file", e);
+				None
+			},
 		};
-		Ok((
-			ILIAS::get_items(&html),
-			main_text,
-			html.select(&LINKS)
-				.flat_map(|x| x.value().attr("href").map(|x| x.to_owned()))
-				.collect(),
-		))
-	}
-
-	pub async fn get_course_content_tree(&self, ref_id: &str, cmd_node: &str) -> Result<Vec<Object>> {
-		// TODO: this magically does not return sub-folders
-		// opening the same url in browser does show sub-folders?!
-		let url = format!(
-			"{}ilias.php?ref_id={}&cmdClass=ilobjcoursegui&cmd=showRepTree&cmdNode={}&baseClass=ilRepositoryGUI&cmdMode=asynch&exp_cmd=getNodeAsync&node_id=exp_node_rep_exp_{}&exp_cont=il_expl2_jstree_cont_rep_exp&searchterm=",
-			ILIAS_URL, ref_id, cmd_node, ref_id
-		);
-		let html = self.get_html_fragment(&url).await?;
-		let mut items = Vec::new();
+		if let Some(name) = from_header.as_ref() {
+			if let Err(e) = self.state.update(&url.url, |entry| entry.name = Some(name.clone())) {
+				warning!(e);
+			}
+		}
+		from_header
+			.or(Some(text).filter(|x| !x.is_empty()))
+			.unwrap_or_else(|| format!("file_{}", url.query_param("file_id").unwrap_or_default()))
+	}
+
+	/// Returns the URLs of item blocks that are only loaded asynchronously (via AJAX) by the browser.
+	fn get_lazy_blocks(html: &Html) -> Vec<String> {
+		let placeholders = html
+			.select(&CONTAINER_BLOCK_IDS)
+			.flat_map(|x| x.value().id())
+			.collect::<HashSet<_>>();
+		let mut urls = Vec::new();
+		for script in html.select(&SCRIPTS) {
+			let script = script.text().collect::<String>();
+			for cap in LAZY_BLOCK_REGEX.captures_iter(&script) {
+				if placeholders.contains(&cap[2]) {
+					urls.push(cap[1].replace("&amp;", "&").replace("\\/", "/"));
+				}
+			}
+		}
+		urls
+	}
+
+	/// Returns the content tree of the course, all expandable nodes (folders, groups, ..) are expanded recursively.
+	pub async fn get_course_content_tree(&self, ref_id: &str, cmd_node: &str) -> Result<Vec<TreeNode>> {
+		let fetch = |node: String| {
+			let url = format!(
+				"{}ilias.php?ref_id={}&cmdClass=ilobjcoursegui&cmd=showRepTree&cmdNode={}&baseClass=ilRepositoryGUI&cmdMode=asynch&exp_cmd=getNodeAsync&node_id=exp_node_rep_exp_{}&exp_cont=il_expl2_jstree_cont_rep_exp&searchterm=",
+				ilias_url(), ref_id, cmd_node, node
+			);
+			async move { Ok(self.download(&url).await?.text().await?) }.boxed()
+		};
+		expand_tree_node(ref_id.to_owned(), &fetch).await
+	}
+
+	/// Remember the content of the folders in the content tree, to avoid loading their pages.
+	/// Returns the objects of the top level.
+	pub fn store_content_tree(&self, nodes: Vec<TreeNode>) -> Vec<Object> {
+		nodes
+			.into_iter()
+			.map(|node| {
+				if matches!(node.object, Folder { .. }) {
+					let children = self.store_content_tree(node.children);
+					self.tree_children
+						.lock()
+						.unwrap()
+						.insert(node.object.url().ref_id.clone(), children);
+				}
+				node.object
+			})
+			.collect()
+	}
+
+	/// Returns the content of the folder, if it was loaded from the content tree.
+	pub fn take_tree_children(&self, ref_id: &str) -> Option<Vec<Object>> {
+		self.tree_children.lock().unwrap().remove(ref_id)
+	}
+}
+
+/// Names and values of form fields
+type FormFields = Vec<(String, String)>;
+
+/// Returns the form action and fields to submit, if the page asks to accept the copyright / license terms of a file.
+fn download_confirmation_form(text: &str, page_url: &Url) -> Result<Option<(Url, FormFields)>> {
+	let html = Html::parse_document(text);
+	let form = html.select(&FORMS).find(|form| {
+		form.select(&FORM_INPUTS).any(|input| {
+			let attr = |name| input.value().attr(name).unwrap_or("").to_lowercase();
+			attr("name").contains("accept") || attr("id").contains("accept") || attr("name").contains("copyright")
+		})
+	});
+	let form = match form {
+		Some(form) => form,
+		None => return Ok(None),
+	};
+	let action = page_url.join(form.value().attr("action").unwrap_or(""))?;
+	let mut fields = Vec::new();
+	let mut submitted = false;
+	for input in form.select(&FORM_INPUTS) {
+		let input = input.value();
+		let name = match input.attr("name") {
+			Some(name) => name.to_owned(),
+			None => continue,
+		};
+		let default_type = if input.name() == "button" { "submit" } else { "text" };
+		match input.attr("type").unwrap_or(default_type) {
+			"checkbox" => fields.push((name, input.attr("value").unwrap_or("1").to_owned())),
+			// only send the first submit button
+			"submit" if submitted => {},
+			"submit" => {
+				submitted = true;
+				fields.push((name, input.attr("value").unwrap_or("").to_owned()));
+			},
+			_ => fields.push((name, input.attr("value").unwrap_or("").to_owned())),
+		}
+	}
+	Ok(Some((action, fields)))
+}
+
+/// Object in the content tree, with the content of expandable nodes
+#[derive(Debug)]
+pub struct TreeNode {
+	pub object: Object,
+	pub children: Vec<TreeNode>,
+}
+
+/// Result of a file download
+pub struct Download {
+	/// Modification time reported by the server
+	pub modified: Option<SystemTime>,
+	/// MD5 checksum of the file (only computed with `--verify-checksums`)
+	pub md5: Option<String>,
+}
+
+/// Hash the first `len` bytes of the file.
+async fn md5_prefix(path: &Path, len: u64) -> Result<Md5> {
+	let path = path.to_owned();
+	tokio::task::spawn_blocking(move || {
+		let mut hasher = Md5::new();
+		let file = std::fs::File::open(path)?;
+		std::io::copy(&mut std::io::Read::take(file, len), &mut hasher)?;
+		Ok(hasher)
+	})
+	.await?
+}
+
+/// Loads the children of the node with the given ref_id using `fetch` (returns the `getNodeAsync` response),
+/// and recursively expands all closed nodes.
+fn expand_tree_node<'a>(
+	ref_id: String,
+	fetch: &'a (dyn Fn(String) -> BoxFuture<'a, Result<String>> + Sync),
+) -> BoxFuture<'a, Result<Vec<TreeNode>>> {
+	async move {
+		let html = fetch(ref_id.clone()).await?;
+		let nodes = {
+			let html = Html::parse_fragment(&html);
+			if ILIAS::is_error_response(&html) {
+				return Err(anyhow!("ILIAS error"));
+			}
+			parse_tree_nodes(&html, &ref_id)?
+		};
+		resolve_tree_nodes(nodes, fetch).await
+	}
+	.boxed()
+}
+
+fn resolve_tree_nodes<'a>(
+	nodes: Vec<ParsedTreeNode>,
+	fetch: &'a (dyn Fn(String) -> BoxFuture<'a, Result<String>> + Sync),
+) -> BoxFuture<'a, Result<Vec<TreeNode>>> {
+	async move {
+		let mut tree = Vec::new();
+		for node in nodes {
+			let children = if node.closed {
+				expand_tree_node(node.ref_id, fetch).await?
+			} else {
+				resolve_tree_nodes(node.children, fetch).await?
+			};
+			tree.push(TreeNode {
+				object: node.object,
+				children,
+			});
+		}
+		Ok(tree)
+	}
+	.boxed()
+}
+
+/// Node of a content tree response. The children of closed nodes are not included and have to be requested.
+struct ParsedTreeNode {
+	object: Object,
+	ref_id: String,
+	closed: bool,
+	children: Vec<ParsedTreeNode>,
+}
+
+/// Parses the top level nodes of a `getNodeAsync` response (the node `parent` itself is skipped).
+fn parse_tree_nodes(html: &Html, parent: &str) -> Result<Vec<ParsedTreeNode>> {
+	let mut items = Vec::new();
+	if html.select(&TREE_NODES).next().is_none() {
+		// plain list of links
 		for link in html.select(&LINKS) {
-			if link.value().attr("href").is_some() {
-				items.push(Object::from_link(link, link)?);
+			if let Some(href) = link.value().attr("href") {
+				items.push(ParsedTreeNode {
+					ref_id: URL::from_href(href)?.ref_id,
+					object: Object::from_link(link, link)?,
+					closed: false,
+					children: Vec::new(),
+				});
 			} // else: disabled course
 		}
-		Ok(items)
+		return Ok(items);
+	}
+	for node in html.select(&TREE_NODES).filter(|x| parent_tree_node(*x).is_none()) {
+		if node_ref_id(node) == parent {
+			// the requested node itself, its children are included
+			items.extend(parse_tree_children(node)?);
+		} else {
+			items.extend(parse_tree_node(node)?);
+		}
+	}
+	Ok(items)
+}
+
+fn parse_tree_node(node: ElementRef) -> Result<Option<ParsedTreeNode>> {
+	let ref_id = node_ref_id(node).to_owned();
+	let link = match node.select(&LINKS).next() {
+		Some(link) => link,
+		None => return Ok(None),
+	};
+	let object = match link.value().attr("href") {
+		Some(href) if href != "#" => Object::from_link(link, link)?,
+		// expandable nodes (e.g. sub-folders) are not always linked, their type is shown by the icon
+		_ => {
+			let icon = node
+				.select(&IMAGES)
+				.next()
+				.and_then(|x| x.value().attr("src"))
+				.and_then(|x| TREE_ICON.captures(x));
+			match icon {
+				Some(icon) => {
+					let name = link.text().collect::<String>().replace('/', "-").trim().to_owned();
+					let url = URL::from_href(&format!("goto.php?target={}_{}", &icon[1], ref_id))?;
+					Object::from_url(url, name, None)?
+				},
+				None => return Ok(None), // disabled course
+			}
+		},
+	};
+	Ok(Some(ParsedTreeNode {
+		object,
+		closed: node.value().classes().any(|x| x == "jstree-closed"),
+		children: parse_tree_children(node)?,
+		ref_id,
+	}))
+}
+
+/// Parses the nodes directly below `node` (included if the node is open).
+fn parse_tree_children(node: ElementRef) -> Result<Vec<ParsedTreeNode>> {
+	let mut children = Vec::new();
+	for child in node.select(&TREE_NODES).filter(|x| parent_tree_node(*x) == Some(node)) {
+		children.extend(parse_tree_node(child)?);
 	}
+	Ok(children)
+}
+
+fn parent_tree_node(node: ElementRef) -> Option<ElementRef> {
+	node.ancestors()
+		.filter_map(ElementRef::wrap)
+		.find(|x| x.value().name() == "li" && x.value().id().is_some_and(|x| x.starts_with("exp_node_rep_exp_")))
+}
+
+fn node_ref_id(node: ElementRef<'_>) -> &str {
+	&node.value().id().unwrap()["exp_node_rep_exp_".len()..]
 }
 
 #[derive(Debug)]
@@ -314,14 +1270,20 @@ pub enum Object {
 	Course { name: String, url: URL },
 	Folder { name: String, url: URL },
 	Dashboard { url: URL },
-	File { name: String, url: URL },
+	File { name: String, url: URL, version: Option<String> },
 	Forum { name: String, url: URL },
+	Blog { name: String, url: URL },
+	DataCollection { name: String, url: URL },
 	Thread { url: URL },
 	Wiki { name: String, url: URL },
+	Glossary { name: String, url: URL },
+	QuestionPool { name: String, url: URL },
 	ExerciseHandler { name: String, url: URL },
 	Weblink { name: String, url: URL },
 	Survey { name: String, url: URL },
+	Test { name: String, url: URL },
 	Presentation { name: String, url: URL },
+	MediaCast { name: String, url: URL },
 	PluginDispatch { name: String, url: URL },
 	Video { url: URL },
 	Generic { name: String, url: URL },
@@ -336,10 +1298,16 @@ impl Object {
 			| Folder { name, .. }
 			| File { name, .. }
 			| Forum { name, .. }
+			| Blog { name, .. }
+			| DataCollection { name, .. }
 			| Wiki { name, .. }
+			| Glossary { name, .. }
+			| QuestionPool { name, .. }
 			| Weblink { name, .. }
 			| Survey { name, .. }
+			| Test { name, .. }
 			| Presentation { name, .. }
+			| MediaCast { name, .. }
 			| ExerciseHandler { name, .. }
 			| PluginDispatch { name, .. }
 			| Generic { name, .. } => name,
@@ -349,6 +1317,28 @@ impl Object {
 		}
 	}
 
+	/// Name of the object on disk. Removes the version suffix of files if `--strip-version-suffix` is active
+	/// and prefixes directories with their kind if `--prefix-kind` is active.
+	pub fn file_name(&self, opt: &Opt) -> Cow<'_, str> {
+		if opt.prefix_kind && self.is_dir() {
+			return Cow::Owned(format!("[{}] {}", self.kind(), self.name()));
+		}
+		match self {
+			File {
+				name,
+				version: Some(version),
+				..
+			} if opt.strip_version_suffix => {
+				let suffix = format!("_v{}", version);
+				match name.rfind(&suffix) {
+					Some(idx) => Cow::Owned(format!("{}{}", &name[..idx], &name[idx + suffix.len()..])),
+					None => Cow::Borrowed(name),
+				}
+			},
+			_ => Cow::Borrowed(self.name()),
+		}
+	}
+
 	pub fn url(&self) -> &URL {
 		match self {
 			Course { url, .. }
@@ -356,11 +1346,17 @@ impl Object {
 			| Dashboard { url }
 			| File { url, .. }
 			| Forum { url, .. }
+			| Blog { url, .. }
+			| DataCollection { url, .. }
 			| Thread { url }
 			| Wiki { url, .. }
+			| Glossary { url, .. }
+			| QuestionPool { url, .. }
 			| Weblink { url, .. }
 			| Survey { url, .. }
+			| Test { url, .. }
 			| Presentation { url, .. }
+			| MediaCast { url, .. }
 			| ExerciseHandler { url, .. }
 			| PluginDispatch { url, .. }
 			| Video { url }
@@ -375,11 +1371,17 @@ impl Object {
 			Dashboard { .. } => "dashboard",
 			File { .. } => "file",
 			Forum { .. } => "forum",
+			Blog { .. } => "blog",
+			DataCollection { .. } => "data collection",
 			Thread { .. } => "thread",
 			Wiki { .. } => "wiki",
+			Glossary { .. } => "glossary",
+			QuestionPool { .. } => "question pool",
 			Weblink { .. } => "weblink",
 			Survey { .. } => "survey",
+			Test { .. } => "test",
 			Presentation { .. } => "presentation",
+			MediaCast { .. } => "media cast",
 			ExerciseHandler { .. } => "exercise handler",
 			PluginDispatch { .. } => "plugin dispatch",
 			Video { .. } => "video",
@@ -394,13 +1396,37 @@ impl Object {
 				| Folder { .. } | Dashboard { .. }
 				| Forum { .. } | Thread { .. }
 				| Wiki { .. } | ExerciseHandler { .. }
+				| Glossary { .. } | QuestionPool { .. }
+				| Survey { .. } | Presentation { .. }
+				| MediaCast { .. } | PluginDispatch { .. }
+				| Test { .. } | Blog { .. }
+				| DataCollection { .. }
+		)
+	}
+
+	/// Whether processing the object finds other objects (e.g. the threads of a forum).
+	/// `--list` only processes these objects.
+	pub fn has_children(&self) -> bool {
+		matches!(
+			self,
+			Course { .. }
+				| Folder { .. }
+				| Dashboard { .. }
+				| Forum { .. }
+				| ExerciseHandler { .. }
+				| MediaCast { .. }
 				| PluginDispatch { .. }
+				| Generic { .. }
 		)
 	}
 
 	pub fn from_link(item: ElementRef, link: ElementRef) -> Result<Self> {
 		let name = link.text().collect::<String>().replace('/', "-").trim().to_owned();
 		let url = URL::from_href(link.value().attr("href").context("link missing href")?)?;
+		// courses and groups the user is not a member of (only linked from elsewhere) can be joined
+		if item.select(&JOIN_BUTTON).next().is_some() {
+			return Ok(Generic { name, url });
+		}
 		Object::from_url(url, name, Some(item))
 	}
 
@@ -409,7 +1435,7 @@ impl Object {
 			return Ok(Thread { url });
 		}
 
-		if url.url.starts_with("https://ilias.studium.kit.edu/goto.php") {
+		if url.url.starts_with(&format!("{}goto.php", ilias_url())) {
 			let target = url.target.as_deref().unwrap_or("NONE");
 			if target.starts_with("wiki_") {
 				return Ok(Wiki {
@@ -432,10 +1458,40 @@ impl Object {
 				url.ref_id = ref_id.to_owned();
 				return Ok(Forum { name, url });
 			}
+			if target.starts_with("glo_") {
+				let ref_id = url.target.as_ref().unwrap().split('_').nth(1).unwrap();
+				url.ref_id = ref_id.to_owned();
+				return Ok(Glossary { name, url });
+			}
+			if target.starts_with("qpl_") {
+				let ref_id = url.target.as_ref().unwrap().split('_').nth(1).unwrap();
+				url.ref_id = ref_id.to_owned();
+				return Ok(QuestionPool { name, url });
+			}
 			if target.starts_with("lm_") {
 				// fancy interactive task
 				return Ok(Presentation { name, url });
 			}
+			if target.starts_with("mcst_") {
+				let ref_id = url.target.as_ref().unwrap().split('_').nth(1).unwrap();
+				url.ref_id = ref_id.to_owned();
+				return Ok(MediaCast { name, url });
+			}
+			if target.starts_with("blog_") {
+				let ref_id = url.target.as_ref().unwrap().split('_').nth(1).unwrap();
+				url.ref_id = ref_id.to_owned();
+				return Ok(Blog { name, url });
+			}
+			if target.starts_with("dcl_") {
+				let ref_id = url.target.as_ref().unwrap().split('_').nth(1).unwrap();
+				url.ref_id = ref_id.to_owned();
+				return Ok(DataCollection { name, url });
+			}
+			if target.starts_with("tst_") {
+				let ref_id = url.target.as_ref().unwrap().split('_').nth(1).unwrap();
+				url.ref_id = ref_id.to_owned();
+				return Ok(Test { name, url });
+			}
 			if target.starts_with("fold_") {
 				let ref_id = url.target.as_ref().unwrap().split('_').nth(1).unwrap();
 				url.ref_id = ref_id.to_owned();
@@ -455,31 +1511,75 @@ impl Object {
 						.context("cannot find 3rd file metadata")?
 						.text()
 						.collect::<String>();
-					let version = version.trim();
-					if let Some(v) = version.strip_prefix("Version: ") {
+					let version = version.trim().strip_prefix("Version: ").map(|v| v.to_owned());
+					if let Some(v) = version.as_ref() {
 						name += "_v";
 						name += v;
 					}
 					return Ok(File {
 						name: format!("{}.{}", name, ext.text().collect::<String>().trim()),
 						url,
+						version,
 					});
 				}
 			}
 			return Ok(Generic { name, url });
 		}
 
+		if url.is_sendfile() {
+			return Ok(File {
+				name,
+				url,
+				version: None,
+			});
+		}
+
 		if url.cmd.as_deref() == Some("showThreads") {
 			return Ok(Forum { name, url });
 		}
 
+		if url
+			.query_param("cmdClass")
+			.is_some_and(|x| x.eq_ignore_ascii_case("ilObjQuestionPoolGUI"))
+		{
+			return Ok(QuestionPool { name, url });
+		}
+
+		if url
+			.query_param("cmdClass")
+			.is_some_and(|x| x.eq_ignore_ascii_case("ilObjTestGUI"))
+		{
+			return Ok(Test { name, url });
+		}
+
+		if url
+			.query_param("cmdClass")
+			.is_some_and(|x| x.eq_ignore_ascii_case("ilObjBlogGUI"))
+		{
+			return Ok(Blog { name, url });
+		}
+
+		if url
+			.query_param("cmdClass")
+			.is_some_and(|x| x.eq_ignore_ascii_case("ilObjDataCollectionGUI"))
+		{
+			return Ok(DataCollection { name, url });
+		}
+
 		// class name is *sometimes* in CamelCase
 		Ok(match &*url.baseClass.to_ascii_lowercase() {
 			"ilexercisehandlergui" => ExerciseHandler { name, url },
-			"ililwikihandlergui" => Wiki { name, url },
+			"ililwikihandlergui" | "ilwikihandlergui" => Wiki { name, url },
 			"illinkresourcehandlergui" => Weblink { name, url },
+			"ilglossarypresentationgui" | "ilobjglossarygui" => Glossary { name, url },
 			"ilobjsurveygui" => Survey { name, url },
+			"ilobjtestgui" => Test { name, url },
+			"ilobjbloggui" => Blog { name, url },
+			"ilobjdatacollectiongui" => DataCollection { name, url },
 			"illmpresentationgui" => Presentation { name, url },
+			"ilmediacasthandlergui" | "ilobjmediacastgui" => MediaCast { name, url },
+			// repository root (magazine)
+			"ilrepositorygui" if url.ref_id == "1" => Generic { name, url },
 			"ilrepositorygui" => match url.cmd.as_deref() {
 				Some("view") | Some("render") => Folder { name, url },
 				Some(_) => Generic { name, url },
@@ -494,8 +1594,25 @@ impl Object {
 
 	pub(crate) fn is_ignored_by_option(&self, opt: &Opt) -> bool {
 		(matches!(self, Object::Forum { .. }) && !opt.forum)
+			|| (matches!(self, Object::Wiki { .. }) && !opt.wiki)
+			|| (matches!(self, Object::Survey { .. }) && !opt.survey)
+			|| (matches!(self, Object::Test { .. }) && !opt.tests)
+			|| (matches!(self, Object::Blog { .. }) && !opt.blog)
+			|| (matches!(self, Object::DataCollection { .. }) && !opt.data_collections)
+			|| (matches!(self, Object::Presentation { .. }) && !opt.learning_modules)
+			|| (matches!(self, Object::Glossary { .. }) && !opt.glossary)
+			|| (matches!(self, Object::QuestionPool { .. }) && !opt.question_pools)
 			|| (matches!(self, Object::Video { .. }) && opt.no_videos)
-			|| (matches!(self, Object::File { .. }) && opt.skip_files)
+			|| matches!(self, Object::File { name, .. } if file::is_skipped(Path::new(name), opt))
+			|| (opt.videos_only
+				&& !matches!(
+					self,
+					Object::Course { .. }
+						| Object::Folder { .. }
+						| Object::Dashboard { .. }
+						| Object::PluginDispatch { .. }
+						| Object::Video { .. }
+				))
 	}
 }
 
@@ -523,9 +1640,38 @@ impl URL {
 		}
 	}
 
+	/// Returns true if this is a permanent link (`goto.php?target=..` or `goto_<client>_<target>.html`).
+	pub fn is_goto(&self) -> bool {
+		self.url.starts_with(&format!("{}goto", ilias_url()))
+	}
+
+	/// Returns true if this is a direct link to a file (`cmd=sendfile&file_id=..`).
+	pub fn is_sendfile(&self) -> bool {
+		self.cmd.as_deref() == Some("sendfile")
+			&& (self.query_param("file_id").is_some() || self.query_param("file").is_some())
+	}
+
+	/// Returns the ref_id of the object, also for permanent links (`target=file_123_download`).
+	pub fn object_ref_id(&self) -> Option<String> {
+		if !self.ref_id.is_empty() {
+			return Some(self.ref_id.clone());
+		}
+		let target = self.target.as_deref()?;
+		target.split('_').nth(1).map(ToOwned::to_owned)
+	}
+
+	/// Returns the value of the query parameter.
+	pub fn query_param(&self, key: &str) -> Option<String> {
+		Url::parse(&self.url)
+			.ok()?
+			.query_pairs()
+			.find(|(k, _)| k == key)
+			.map(|(_, v)| v.into_owned())
+	}
+
 	pub fn from_href(href: &str) -> Result<Self> {
-		let url = if !href.starts_with(ILIAS_URL) {
-			Url::parse(&format!("{}{}", ILIAS_URL, href))?
+		let url = if !href.starts_with(ilias_url()) {
+			Url::parse(&format!("{}{}", ilias_url(), href))?
 		} else {
 			Url::parse(href)?
 		};
@@ -554,3 +1700,261 @@ impl URL {
 		})
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_server::{self, Response};
+
+	fn describe(nodes: &[TreeNode], depth: usize, out: &mut Vec<String>) {
+		for node in nodes {
+			let object = &node.object;
+			let line = format!("{} {} {}", object.kind(), object.name(), object.url().ref_id);
+			out.push(format!("{}{}", "  ".repeat(depth), line));
+			describe(&node.children, depth + 1, out);
+		}
+	}
+
+	#[tokio::test]
+	async fn content_tree_is_expanded() {
+		test_server::base_url();
+		let requests = Mutex::new(Vec::new());
+		let fetch = |node: String| {
+			let html = match &*node {
+				"1000" => include_str!("../tests/fixtures/content_tree_course.html"),
+				"1001" => include_str!("../tests/fixtures/content_tree_folder_1001.html"),
+				"1002" => include_str!("../tests/fixtures/content_tree_folder_1002.html"),
+				"1004" => include_str!("../tests/fixtures/content_tree_folder_1004.html"),
+				_ => panic!("unexpected request for node {}", node),
+			};
+			requests.lock().unwrap().push(node);
+			async move { Ok(html.to_owned()) }.boxed()
+		};
+		let tree = expand_tree_node("1000".to_owned(), &fetch).await.unwrap();
+		// open nodes (1003) include their children, only closed nodes are requested
+		assert_eq!(*requests.lock().unwrap(), ["1000", "1001", "1004", "1002"]);
+		let mut lines = Vec::new();
+		describe(&tree, 0, &mut lines);
+		assert_eq!(
+			lines,
+			[
+				"folder Vorlesungen 1001",
+				"  folder Kapitel 1 1004",
+				"    glossary Begriffe 1008",
+				"  weblink Skript online 1007",
+				"folder Übungen 1002",
+				"  exercise handler Übungsblätter 1009",
+				"folder Tutorien 1003",
+				"  forum Tutorienforum 1010",
+				"forum Forum 1005",
+			]
+		);
+	}
+
+	#[test]
+	fn dashboard_skips_joinable_courses() {
+		test_server::base_url();
+		let html = Html::parse_document(include_str!("../tests/fixtures/dashboard.html"));
+		let items = ILIAS::get_items(&html).into_iter().collect::<Result<Vec<_>>>().unwrap();
+		assert_eq!(items.len(), 5);
+		// only the courses and folders the user is a member of are processed
+		let containers = items
+			.iter()
+			.filter(|x| x.is_dir())
+			.map(|x| (x.kind(), x.name(), &*x.url().ref_id))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			containers,
+			[
+				("course", "Softwaretechnik I", "2001"),
+				("folder", "Klausurvorbereitung", "2004")
+			]
+		);
+		// courses and groups that can be joined, and the repository root
+		for name in ["Lineare Algebra II", "Tutorium Gruppe 7", "Magazin"] {
+			let item = items.iter().find(|x| x.name() == name).unwrap();
+			assert!(matches!(item, Generic { .. }), "{:?}", item);
+		}
+	}
+
+	#[test]
+	fn copyright_confirmation_form() {
+		let page_url = Url::parse("https://ilias.studium.kit.edu/goto.php?target=file_3001_download").unwrap();
+		let text = include_str!("../tests/fixtures/copyright_confirmation.html");
+		let (action, fields) = download_confirmation_form(text, &page_url).unwrap().unwrap();
+		assert_eq!(
+			action.as_str(),
+			"https://ilias.studium.kit.edu/ilias.php?ref_id=3001&cmd=post&cmdClass=ilobjfilegui&cmdNode=x1:nk&baseClass=ilrepositorygui&fallbackCmd=confirmDownload"
+		);
+		let fields = fields.iter().map(|(k, v)| (&**k, &**v)).collect::<Vec<_>>();
+		assert_eq!(
+			fields,
+			[
+				("accept_copyright", "1"),
+				("file_id", "3001"),
+				("cmd[confirmedDownload]", "Herunterladen")
+			]
+		);
+
+		// other HTML pages are not confirmations
+		let text = include_str!("../tests/fixtures/dashboard.html");
+		assert!(download_confirmation_form(text, &page_url).unwrap().is_none());
+	}
+
+	#[tokio::test]
+	async fn lazy_blocks_are_loaded() {
+		let page = include_str!("../tests/fixtures/course_lazy_block.html");
+		let block = include_str!("../tests/fixtures/course_lazy_block_items.html");
+		test_server::serve("cmd=getAsynchItemList", Response::html(block));
+		test_server::serve("ref_id=4000&cmd=view", Response::html(page));
+		let (ilias, _dir) = test_server::ilias(&[]).await;
+		let url = URL::from_href("ilias.php?ref_id=4000&cmd=view&cmdClass=ilobjcoursegui&baseClass=ilrepositorygui");
+		let (items, _, _) = ilias.get_course_content(&url.unwrap()).await.unwrap();
+		let items = items
+			.into_iter()
+			.map(|x| x.map(|x| format!("{} {}", x.kind(), x.name())))
+			.collect::<Result<Vec<_>>>()
+			.unwrap();
+		assert_eq!(
+			items,
+			[
+				"folder Vorlesungsfolien",
+				"folder Übungsblätter",
+				"forum Fragen zur Übung"
+			]
+		);
+		assert_eq!(
+			test_server::requests("cmd=getAsynchItemList"),
+			["GET /ilias.php?ref_id=4000&cmd=getAsynchItemList&cmdClass=ilobjcoursegui&cmdMode=asynch&block=2&baseClass=ilrepositorygui"]
+		);
+	}
+
+	#[tokio::test]
+	async fn interrupted_download_is_resumed() {
+		test_server::serve_sequence(
+			"target=file_5003_download",
+			vec![
+				// the connection is closed after the first half of the file
+				Response::file(b"Vorles")
+					.header("Content-Length", "12")
+					.header("ETag", "\"5003\""),
+				Response::file(b"ung 3\n")
+					.status(206)
+					.header("Content-Range", "bytes 6-11/12")
+					.header("ETag", "\"5003\""),
+			],
+		);
+		let (ilias, dir) = test_server::ilias(&[]).await;
+		let path = dir.path().join("Vorlesung 3.pdf");
+		let url = "goto.php?target=file_5003_download";
+		assert!(ilias.download_to_file(url, &path).await.is_err());
+		assert_eq!(std::fs::read(&path).unwrap(), b"Vorles");
+		assert!(partial_download_marker(&path).exists());
+
+		ilias.download_to_file(url, &path).await.unwrap();
+		assert_eq!(std::fs::read(&path).unwrap(), b"Vorlesung 3\n");
+		assert!(!partial_download_marker(&path).exists());
+		let requests = test_server::request_heads("target=file_5003_download");
+		assert_eq!(requests.len(), 2);
+		assert!(requests[1].to_lowercase().contains("\nrange: bytes=6-"));
+		assert!(requests[1].to_lowercase().contains("\nif-range: \"5003\""));
+	}
+
+	#[tokio::test]
+	async fn wrong_part_is_not_appended() {
+		test_server::serve_sequence(
+			"target=file_5006_download",
+			vec![
+				Response::file(b"Vorles")
+					.header("Content-Length", "12")
+					.header("ETag", "\"5006\""),
+				Response::file(b"Vorlesung 6\n")
+					.status(206)
+					.header("Content-Range", "bytes 0-11/12"),
+				Response::file(b"Vorlesung 6\n"),
+			],
+		);
+		let (ilias, dir) = test_server::ilias(&[]).await;
+		let path = dir.path().join("Vorlesung 6.pdf");
+		let url = "goto.php?target=file_5006_download";
+		assert!(ilias.download_to_file(url, &path).await.is_err());
+
+		ilias.download_to_file(url, &path).await.unwrap();
+		assert_eq!(std::fs::read(&path).unwrap(), b"Vorlesung 6\n");
+		let requests = test_server::request_heads("target=file_5006_download");
+		assert_eq!(requests.len(), 3);
+		assert!(!requests[2].to_lowercase().contains("\nrange:"));
+	}
+
+	#[tokio::test]
+	async fn incomplete_files_are_detected() {
+		test_server::serve("target=file_5008_download", Response::file(b"Vorlesung 8\n"));
+		test_server::serve("target=file_5009_download", Response::file(b"Vorlesung 9\n"));
+		let (ilias, dir) = test_server::ilias(&[]).await;
+		let path = dir.path().join("Vorlesung.pdf");
+		std::fs::write(&path, b"Vorles").unwrap();
+
+		// size of the previous download
+		let url = "goto.php?target=file_5007_download";
+		ilias.state.update(url, |x| x.size = Some(12)).unwrap();
+		assert!(ilias.is_incomplete(url, &path).await);
+		// size reported by the server
+		assert!(ilias.is_incomplete("goto.php?target=file_5008_download", &path).await);
+
+		std::fs::write(&path, b"Vorlesung 9\n").unwrap();
+		let url = "goto.php?target=file_5009_download";
+		assert!(!ilias.is_incomplete(url, &path).await);
+		assert_eq!(ilias.state.get(url).unwrap().size, Some(12));
+		assert!(!ilias.is_incomplete(url, &path).await);
+		let requests = test_server::requests("target=file_5009_download");
+		assert_eq!(requests, ["HEAD /goto.php?target=file_5009_download"]);
+	}
+
+	#[tokio::test]
+	async fn failing_hook_aborts_in_strict_mode() {
+		let path = Path::new("Vorlesung 4.pdf");
+		let (ilias, _dir) = test_server::ilias(&["--post-download-hook", "false"]).await;
+		ilias.run_post_download_hook(path, "file", "").await.unwrap();
+		assert!(!queue::is_aborted());
+
+		let (ilias, _dir) = test_server::ilias(&["--post-download-hook", "false", "--strict"]).await;
+		let result = ilias.run_post_download_hook(path, "file", "").await;
+		assert!(result.unwrap_err().to_string().contains("post-download hook failed"));
+		assert!(queue::is_aborted());
+	}
+
+	#[tokio::test]
+	async fn recent_objects_are_loaded_from_feed() {
+		let feed = include_bytes!("../tests/fixtures/course_feed.xml");
+		test_server::serve("feed.php?client_id=test&ref_id=6000", Response::file(feed));
+		let (ilias, _dir) = test_server::ilias(&["--since", "2024-10-01"]).await;
+		// the client ID is detected using the cookie set by ILIAS
+		let cookie = cookie_store::RawCookie::parse("ilClientId=test").unwrap();
+		let url = Url::parse(test_server::base_url()).unwrap();
+		ilias.cookies.lock().unwrap().insert_raw(&cookie, &url).unwrap();
+		ilias.load_recent_objects("6000").await.unwrap();
+
+		let is_recent = |href| ilias.is_recent(&URL::from_href(href).unwrap());
+		assert!(is_recent("goto.php?target=file_6001_download"));
+		// older than --since / not in the feed
+		assert!(!is_recent("goto.php?target=file_6002_download"));
+		assert!(!is_recent("goto.php?target=file_6003_download"));
+	}
+
+	#[test]
+	fn retry_after_is_limited() {
+		let retry_after = |value: &str| {
+			let resp = http::Response::builder()
+				.status(429)
+				.header("Retry-After", value)
+				.body("")
+				.unwrap();
+			retry_after(&reqwest::Response::from(resp))
+		};
+		assert_eq!(retry_after("120"), Some(Duration::from_secs(120)));
+		assert_eq!(retry_after("86400"), Some(MAX_RETRY_AFTER));
+		assert_eq!(retry_after("Thu, 01 Jan 2015 00:00:00 GMT"), Some(Duration::ZERO));
+		assert_eq!(retry_after("Fri, 31 Dec 9999 23:59:59 GMT"), Some(MAX_RETRY_AFTER));
+		assert_eq!(retry_after("soon"), None);
+	}
+}
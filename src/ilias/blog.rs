@@ -0,0 +1,151 @@
+use std::{collections::HashSet, path::Path, sync::Arc};
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use scraper::{ElementRef, Selector};
+
+use crate::{
+	handle_gracefully,
+	queue::spawn,
+	util::{absolute_url, body_stream, escape_html, file_escape, write_html, write_stream_to_file},
+};
+
+use super::{
+	exercise::parse_date,
+	thread::{attribute_value, image_file_name, local_url},
+	ILIAS, URL,
+};
+
+static LINKS: Lazy<Selector> = Lazy::new(|| Selector::parse("a").unwrap());
+static IMAGES: Lazy<Selector> = Lazy::new(|| Selector::parse("img").unwrap());
+static LIST_ITEM: Lazy<Selector> = Lazy::new(|| Selector::parse(".ilBlogListItem").unwrap());
+static LIST_ITEM_TITLE: Lazy<Selector> = Lazy::new(|| Selector::parse(".ilBlogListItemTitle").unwrap());
+static LIST_ITEM_DATE: Lazy<Selector> = Lazy::new(|| Selector::parse(".ilBlogListItemSubTitle").unwrap());
+static POSTING: Lazy<Selector> = Lazy::new(|| Selector::parse(".ilBlogPosting").unwrap());
+static CONTENT: Lazy<Selector> = Lazy::new(|| Selector::parse("#il_center_col").unwrap());
+
+/// A blog posting as listed on the overview page
+struct Posting {
+	id: String,
+	title: String,
+	date: String,
+	url: URL,
+}
+
+pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
+	if !ilias.opt.blog {
+		return Ok(());
+	}
+	// the overview only shows the postings of one month, the other months are linked
+	let mut months = vec![url.url.clone()];
+	let mut seen_months = HashSet::new();
+	let mut seen_postings = HashSet::new();
+	let mut postings = Vec::new();
+	while let Some(month) = months.pop() {
+		let html = ilias.get_html(&month).await?;
+		for link in html.select(&LINKS) {
+			let href = match link.value().attr("href") {
+				Some(href) => href,
+				None => continue,
+			};
+			let link_url = URL::from_href(href)?;
+			if let Some(month) = link_url.query_param("bmn") {
+				if seen_months.insert(month) {
+					months.push(link_url.url);
+				}
+			}
+		}
+		for item in html.select(&LIST_ITEM) {
+			let text = |selector| {
+				item.select(selector)
+					.next()
+					.map(|x: ElementRef| x.text().collect::<String>().trim().to_owned())
+					.unwrap_or_default()
+			};
+			let (title, date) = (text(&LIST_ITEM_TITLE), text(&LIST_ITEM_DATE));
+			let link = item
+				.select(&LINKS)
+				.flat_map(|x| x.value().attr("href"))
+				.map(URL::from_href)
+				.filter_map(Result::ok)
+				.find_map(|x| Some((x.query_param("blpg")?, x)));
+			if let Some((id, url)) = link {
+				if seen_postings.insert(id.clone()) {
+					postings.push(Posting { id, title, date, url });
+				}
+			}
+		}
+	}
+	if postings.is_empty() {
+		log!(1, "No postings in blog {}", relative_path.display());
+		return Ok(());
+	}
+	let mut all_images = Vec::new();
+	for posting in postings {
+		let (html, images) = {
+			let page = ilias.get_html(&posting.url.url).await?;
+			let content = page
+				.select(&POSTING)
+				.next()
+				.or_else(|| page.select(&CONTENT).next())
+				.context("blog posting not found")?;
+			// images are saved next to the posting, the saved posting refers to these copies
+			let mut images = Vec::new();
+			for image in content.select(&IMAGES) {
+				if let Some(src) = image.value().attr("src") {
+					images.push((image_file_name(&posting.id, src), src.to_owned()));
+				}
+			}
+			let mut html = content.inner_html();
+			for (file_name, src) in &images {
+				html = html.replace(
+					&format!("src=\"{}\"", attribute_value(src)),
+					&format!("src=\"{}\"", local_url(file_name)),
+				);
+			}
+			for href in content.select(&LINKS).flat_map(|x| x.value().attr("href")) {
+				html = html.replace(
+					&format!("href=\"{}\"", attribute_value(href)),
+					&format!("href=\"{}\"", attribute_value(&absolute_url(href))),
+				);
+			}
+			(html, images)
+		};
+		// sort the postings chronologically by name
+		let date = parse_date(&posting.date)
+			.map(|x| x.date().to_string())
+			.unwrap_or(posting.date);
+		let name = file_escape(&format!("{}_{}.html", date, posting.title));
+		let path = path.join(&name);
+		let relative_path = relative_path.join(&name);
+		let data = format!("<!DOCTYPE html>\n<h1>{}</h1>\n{}", escape_html(&posting.title), html);
+		let compress = ilias.opt.compress_html;
+		spawn(handle_gracefully(path.clone(), async move {
+			log!(0, "Writing {}", relative_path.display());
+			write_html(&path, &data, compress)
+				.await
+				.context("failed to write blog posting")
+		}));
+		all_images.extend(images);
+	}
+	let mut seen_images = HashSet::new();
+	for (file_name, image) in all_images {
+		if !seen_images.insert(file_name.clone()) {
+			continue;
+		}
+		let path = path.join(&file_name);
+		let relative_path = relative_path.join(file_name);
+		if ilias.opt.dry_run {
+			log!(0, "Would download {}", relative_path.display());
+			continue;
+		}
+		let dl = ilias.download(&URL::from_href(&image)?.url).await?;
+		spawn(handle_gracefully(path.clone(), async move {
+			log!(0, "Writing {}", relative_path.display());
+			write_stream_to_file(&path, body_stream(dl))
+				.await
+				.context("failed to write blog image")
+		}));
+	}
+	Ok(())
+}
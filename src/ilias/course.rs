@@ -7,20 +7,27 @@ use regex::Regex;
 use crate::{
 	process_gracefully,
 	queue::spawn,
-	util::{file_escape, write_file_data},
+	util::{file_escape, write_ilias_page},
 };
 
-use super::{ILIAS, URL};
+use super::{folder::flatten, ILIAS, URL};
 
 static CMD_NODE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"cmdNode=uf:\w\w"#).unwrap());
 
 pub async fn download(path: PathBuf, ilias: Arc<ILIAS>, url: &URL, name: &str) -> Result<()> {
+	ilias
+		.load_recent_objects(&url.ref_id)
+		.await
+		.context("failed to load RSS feed of the course (--since)")?;
 	let content = if ilias.opt.content_tree {
 		let html = ilias.download(&url.url).await?.text().await?;
 		let cmd_node = CMD_NODE_REGEX.find(&html).context("can't find cmdNode")?.as_str()[8..].to_owned();
 		let content_tree = ilias.get_course_content_tree(&url.ref_id, &cmd_node).await;
 		match content_tree {
-			Ok(tree) => (tree.into_iter().map(Result::Ok).collect(), None),
+			Ok(tree) => {
+				let items = ilias.store_content_tree(tree);
+				(items.into_iter().map(Result::Ok).collect(), None)
+			},
 			Err(e) => {
 				// some folders are hidden on the course page and can only be found via the RSS feed / recent activity / content tree sidebar
 				// TODO: this is probably never the case for folders?
@@ -36,17 +43,25 @@ pub async fn download(path: PathBuf, ilias: Arc<ILIAS>, url: &URL, name: &str) -
 		let (items, main_text, _) = ilias.get_course_content(url).await?;
 		(items, main_text)
 	};
+	let (mut items, main_text) = content;
+	if flatten(&path, &ilias, &mut items).await? {
+		return Ok(());
+	}
 	if ilias.opt.save_ilias_pages {
-		if let Some(s) = content.1.as_ref() {
+		if let Some(s) = main_text.as_ref() {
 			let path = path.join("course.html");
-			write_file_data(&path, &mut s.as_bytes())
+			write_ilias_page(&path, s, &ilias.opt)
 				.await
 				.context("failed to write course page html")?;
 		}
 	}
-	for item in content.0 {
+	for item in items {
 		let item = item?;
-		let path = path.join(file_escape(item.name()));
+		if !item.is_dir() && !ilias.is_recent(item.url()) {
+			log!(1, "Skipping {} (not changed recently)", item.name());
+			continue;
+		}
+		let path = path.join(file_escape(&item.file_name(&ilias.opt)));
 		let ilias = Arc::clone(&ilias);
 		spawn(process_gracefully(ilias, path, item));
 	}
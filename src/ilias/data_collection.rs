@@ -0,0 +1,155 @@
+use std::{
+	collections::{BTreeMap, HashSet, VecDeque},
+	path::Path,
+	sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use reqwest::Url;
+use scraper::{ElementRef, Selector};
+
+use crate::util::write_file_data;
+
+use super::{ILIAS, URL};
+
+static TABLE: Lazy<Selector> = Lazy::new(|| Selector::parse(".ilTableOuter table").unwrap());
+static TABLE_HEADER: Lazy<Selector> = Lazy::new(|| Selector::parse("thead th").unwrap());
+static TABLE_ROW: Lazy<Selector> = Lazy::new(|| Selector::parse("tbody tr").unwrap());
+static TABLE_CELLS: Lazy<Selector> = Lazy::new(|| Selector::parse("td").unwrap());
+static TABLE_PAGES: Lazy<Selector> =
+	Lazy::new(|| Selector::parse("div.ilTableNav > table > tbody > tr > td > a").unwrap());
+
+pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
+	if !ilias.opt.data_collections {
+		return Ok(());
+	}
+	let mut header = Vec::new();
+	// rows of each page, by the offset of the page's first record
+	let mut rows = BTreeMap::new();
+	let mut pages = VecDeque::from([url.url.clone()]);
+	let mut visited_pages = HashSet::from([page_key(&url.url)]);
+	while let Some(page) = pages.pop_front() {
+		let html = ilias.get_html(&page).await?;
+		// (the navigation is a table too)
+		let table = html.select(&TABLE).find(|x| x.select(&TABLE_HEADER).next().is_some());
+		let table = match table {
+			Some(table) => table,
+			None if page == url.url => {
+				log!(1, "No table found in data collection {}", relative_path.display());
+				return Ok(());
+			},
+			None => {
+				warning!(format => "no table found on page {} of data collection {}", page, relative_path.display());
+				continue;
+			},
+		};
+		if header.is_empty() {
+			header = table.select(&TABLE_HEADER).map(cell_text).collect();
+		}
+		let page_rows = rows
+			.entry((page_offset(&page).unwrap_or(u64::MAX), page))
+			.or_insert_with(Vec::new);
+		for row in table.select(&TABLE_ROW) {
+			let cells = row.select(&TABLE_CELLS).map(cell_text).collect::<Vec<_>>();
+			// skip the "no records" message (a single cell spanning the whole table)
+			if cells.len() > 1 || header.len() <= 1 {
+				page_rows.push(cells);
+			}
+		}
+		// the other records are on the following pages
+		// (the link to the first page differs from the URL of the data collection, compare the offsets)
+		for href in html.select(&TABLE_PAGES).flat_map(|x| x.value().attr("href")) {
+			let href = URL::from_href(href)?.url;
+			if visited_pages.insert(page_key(&href)) {
+				pages.push_back(href);
+			}
+		}
+	}
+	let mut csv = String::new();
+	for row in std::iter::once(&header).chain(rows.values().flatten()) {
+		csv += &row.iter().map(|x| csv_field(x)).collect::<Vec<_>>().join(",");
+		csv += "\r\n";
+	}
+	log!(0, "Writing {}", relative_path.join("table.csv").display());
+	write_file_data(path.join("table.csv"), &mut csv.as_bytes())
+		.await
+		.context("failed to write data collection")?;
+	Ok(())
+}
+
+/// Offset of the first record shown on the page, from the table navigation parameter
+/// (`<table id>_table_nav=<order field>:<order direction>:<offset>`). The first page has none.
+fn page_offset(url: &str) -> Option<u64> {
+	let url = Url::parse(url).ok()?;
+	let nav = url.query_pairs().find(|(key, _)| key.ends_with("_table_nav"));
+	match nav {
+		Some((_, value)) => value.rsplit(':').next()?.parse().ok(),
+		None => Some(0),
+	}
+}
+
+/// Identifies the page: links to the same page may differ in other parameters.
+fn page_key(url: &str) -> String {
+	page_offset(url).map_or_else(|| url.to_owned(), |x| x.to_string())
+}
+
+fn cell_text(cell: ElementRef) -> String {
+	cell.text()
+		.flat_map(str::split_whitespace)
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
+/// Quote the field if necessary (RFC 4180).
+fn csv_field(field: &str) -> String {
+	if field.contains(&[',', '"', '\r', '\n'][..]) {
+		format!("\"{}\"", field.replace('"', "\"\""))
+	} else {
+		field.to_owned()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_server::{self, Response};
+
+	#[tokio::test]
+	async fn pages_are_written_in_order() {
+		let page = |x| Response::html(x);
+		test_server::serve(
+			"title%3Aasc%3A20&",
+			page(include_str!("../../tests/fixtures/data_collection_page_2.html")),
+		);
+		test_server::serve(
+			"title%3Aasc%3A40&",
+			page(include_str!("../../tests/fixtures/data_collection_page_3.html")),
+		);
+		// the last page has no table
+		test_server::serve("title%3Aasc%3A60&", page("<!DOCTYPE html>\n<p>Keine Einträge</p>"));
+		test_server::serve(
+			"ref_id=7000&cmd=listRecords&baseClass",
+			page(include_str!("../../tests/fixtures/data_collection_page_1.html")),
+		);
+		let (ilias, dir) = test_server::ilias(&["--data-collections"]).await;
+		let url = URL::from_href("ilias.php?ref_id=7000&cmd=listRecords&baseClass=ilrepositorygui").unwrap();
+		download(dir.path(), Path::new("Literaturliste"), Arc::new(ilias), &url)
+			.await
+			.unwrap();
+		let csv = std::fs::read_to_string(dir.path().join("table.csv")).unwrap();
+		assert_eq!(
+			csv.lines().collect::<Vec<_>>(),
+			[
+				"Titel,Autor,Jahr",
+				"Analysis 1,Forster,2016",
+				"Lineare Algebra,\"Fischer, Gerd\",2014",
+				"Numerik,Deuflhard,2019",
+				"Stochastik,Georgii,2015",
+				"Topologie,Jänich,2005"
+			]
+		);
+		// the link to the first page is not followed
+		assert_eq!(test_server::requests("ref_id=7000").len(), 4);
+	}
+}
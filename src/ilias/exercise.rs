@@ -1,66 +1,246 @@
-use std::{collections::HashSet, path::Path, sync::Arc};
+use std::{
+	collections::HashSet,
+	convert::TryFrom,
+	path::Path,
+	sync::{Arc, Mutex},
+};
 
 use anyhow::{Context, Result};
 use once_cell::sync::Lazy;
-use scraper::Selector;
+use regex::Regex;
+use scraper::{ElementRef, Selector};
+use time::{Date, Duration, Month, OffsetDateTime, PrimitiveDateTime, Time};
+use tokio::fs;
 
-use crate::{process_gracefully, queue::spawn, util::file_escape};
+use crate::{
+	handle_gracefully,
+	ical::Event,
+	process_gracefully,
+	queue::spawn,
+	util::{body_stream, file_escape, write_file_data, write_stream_to_file},
+};
 
 use super::{Object, ILIAS, URL};
 
 static LINKS: Lazy<Selector> = Lazy::new(|| Selector::parse("a").unwrap());
 static FORM_GROUP: Lazy<Selector> = Lazy::new(|| Selector::parse(".form-group").unwrap());
 static FORM_NAME: Lazy<Selector> = Lazy::new(|| Selector::parse(".il_InfoScreenProperty").unwrap());
+static IMAGES: Lazy<Selector> = Lazy::new(|| Selector::parse("img").unwrap());
+static ASSIGNMENT: Lazy<Selector> = Lazy::new(|| Selector::parse(".il_VAccordionInnerContainer").unwrap());
+static ASSIGNMENT_TITLE: Lazy<Selector> = Lazy::new(|| Selector::parse(".ilAssignmentHeader").unwrap());
+static FORM_VALUE: Lazy<Selector> = Lazy::new(|| Selector::parse(".il_InfoScreenPropertyValue").unwrap());
+/// e.g. "17. Jan 2024, 23:59" or "Heute, 12:00"
+static DATE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?:(\d{1,2})\. (\w+) (\d{4})|(\w+)), (\d{1,2}):(\d{2})").unwrap());
+
+/// Deadlines of all exercises (`--deadlines-ical`)
+pub static DEADLINES: Lazy<Mutex<Vec<Event>>> = Lazy::new(Mutex::default);
+
+/// Labels of the assignment properties saved in grades.json (German and English UI)
+const GRADE_LABELS: &[&str] = &[
+	"status",
+	"note",
+	"mark",
+	"grade",
+	"punkte",
+	"points",
+	"bewertung",
+	"kommentar",
+	"comment",
+];
+
+/// Labels of the assignment deadline (German and English UI)
+const DEADLINE_LABELS: &[&str] = &[
+	"abgabetermin",
+	"ende der bearbeitungszeit",
+	"deadline",
+	"end of editing time",
+];
 
 pub async fn download(path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
-	let html = ilias.get_html(&url.url).await?;
-	let mut filenames = HashSet::new();
-	for row in html.select(&FORM_GROUP) {
-		let link = row.select(&LINKS).next();
-		if link.is_none() {
-			continue;
+	let (images, grades, deadlines) = {
+		let html = ilias.get_html(&url.url).await?;
+		let mut filenames = HashSet::new();
+		let mut images = Vec::new();
+		let mut grades = serde_json::Map::new();
+		let mut deadlines = Vec::new();
+		for assignment in html.select(&ASSIGNMENT) {
+			let title = assignment
+				.select(&ASSIGNMENT_TITLE)
+				.next()
+				.map(|x| x.text().collect::<String>().trim().to_owned())
+				.unwrap_or_default();
+			let mut properties = serde_json::Map::new();
+			for row in assignment.select(&FORM_GROUP) {
+				let text = |selector| {
+					row.select(selector)
+						.next()
+						.map(|x: ElementRef| x.text().collect::<String>().trim().to_owned())
+				};
+				if let (Some(label), Some(value)) = (text(&FORM_NAME), text(&FORM_VALUE)) {
+					let lowercase = label.to_lowercase();
+					if DEADLINE_LABELS.iter().any(|x| lowercase.contains(x)) {
+						match parse_date(&value) {
+							Some(time) => deadlines.push((title.clone(), time)),
+							None => log!(1, "Could not parse deadline {:?} of {}", value, title),
+						}
+					}
+					if GRADE_LABELS.iter().any(|x| lowercase.contains(x)) {
+						properties.insert(label, value.into());
+					}
+				}
+			}
+			if !properties.is_empty() {
+				grades.insert(title, properties.into());
+			}
 		}
-		let link = link.unwrap();
-		let href = link.value().attr("href");
-		if href.is_none() {
-			continue;
+		if ilias.opt.download_feedback_images {
+			for assignment in html.select(&ASSIGNMENT) {
+				let title = assignment
+					.select(&ASSIGNMENT_TITLE)
+					.next()
+					.map(|x| x.text().collect::<String>().trim().to_owned())
+					.unwrap_or_default();
+				for image in assignment.select(&IMAGES) {
+					let src = match image.value().attr("src") {
+						// images uploaded to ILIAS (not icons)
+						Some(src) if src.contains("/data/") => src,
+						_ => continue,
+					};
+					let name = src.split('?').next().unwrap_or(src);
+					let name = name.rsplit('/').next().unwrap_or(name);
+					let name = file_escape(&format!("{}_{}", title, name));
+					if filenames.insert(name.clone()) {
+						images.push((name, URL::from_href(src)?));
+					}
+				}
+			}
+		}
+		for row in html.select(&FORM_GROUP) {
+			let link = row.select(&LINKS).next();
+			if link.is_none() {
+				continue;
+			}
+			let link = link.unwrap();
+			let href = link.value().attr("href");
+			if href.is_none() {
+				continue;
+			}
+			let href = href.unwrap();
+			let url = URL::from_href(href)?;
+			let cmd = url.cmd.as_deref().unwrap_or("");
+			if cmd != "downloadFile" && cmd != "downloadGlobalFeedbackFile" && cmd != "downloadFeedbackFile" {
+				continue;
+			}
+			// link is definitely just a download link to the exercise or the solution
+			let name = row
+				.select(&FORM_NAME)
+				.next()
+				.context("link without file name")?
+				.text()
+				.collect::<String>()
+				.trim()
+				.to_owned();
+			let item = Object::File {
+				url,
+				name,
+				version: None,
+			};
+			let mut path = path.to_owned();
+			// handle files with the same name
+			let filename = file_escape(item.name());
+			let mut parts = filename.rsplitn(2, '.');
+			let extension = parts.next().unwrap_or(&filename);
+			let name = parts.next().unwrap_or("");
+			let mut unique_filename = filename.clone();
+			let mut i = 1;
+			while filenames.contains(&unique_filename) {
+				i += 1;
+				if name.is_empty() {
+					unique_filename = format!("{}{}", extension, i);
+				} else {
+					unique_filename = format!("{}{}.{}", name, i, extension);
+				}
+			}
+			filenames.insert(unique_filename.clone());
+			path.push(unique_filename);
+			let ilias = Arc::clone(&ilias);
+			spawn(process_gracefully(ilias, path, item));
+		}
+		(images, grades, deadlines)
+	};
+	if ilias.opt.list {
+		// only the files of the exercise are listed
+		return Ok(());
+	}
+	if ilias.opt.deadlines_ical.is_some() {
+		let exercise = path.strip_prefix(ilias.opt.content_dir()).unwrap_or(path);
+		let mut all_deadlines = DEADLINES.lock().unwrap();
+		for (title, time) in deadlines {
+			all_deadlines.push(Event {
+				summary: format!("Deadline: {}", title),
+				description: format!("{}\n{}", exercise.display(), url.url),
+				time,
+			});
 		}
-		let href = href.unwrap();
-		let url = URL::from_href(href)?;
-		let cmd = url.cmd.as_deref().unwrap_or("");
-		if cmd != "downloadFile" && cmd != "downloadGlobalFeedbackFile" && cmd != "downloadFeedbackFile" {
+	}
+	if !grades.is_empty() {
+		let json = serde_json::to_string_pretty(&grades)?;
+		write_file_data(path.join("grades.json"), &mut json.as_bytes())
+			.await
+			.context("failed to write grades")?;
+	}
+	for (name, url) in images {
+		let path = path.join(&name);
+		if !ilias.opt.force && fs::metadata(&path).await.is_ok() {
 			continue;
 		}
-		// link is definitely just a download link to the exercise or the solution
-		let name = row
-			.select(&FORM_NAME)
-			.next()
-			.context("link without file name")?
-			.text()
-			.collect::<String>()
-			.trim()
-			.to_owned();
-		let item = Object::File { url, name };
-		let mut path = path.to_owned();
-		// handle files with the same name
-		let filename = file_escape(item.name());
-		let mut parts = filename.rsplitn(2, '.');
-		let extension = parts.next().unwrap_or(&filename);
-		let name = parts.next().unwrap_or("");
-		let mut unique_filename = filename.clone();
-		let mut i = 1;
-		while filenames.contains(&unique_filename) {
-			i += 1;
-			if name.is_empty() {
-				unique_filename = format!("{}{}", extension, i);
-			} else {
-				unique_filename = format!("{}{}.{}", name, i, extension);
-			}
+		if ilias.opt.dry_run {
+			log!(0, "Would download {}", name);
+			continue;
 		}
-		filenames.insert(unique_filename.clone());
-		path.push(unique_filename);
-		let ilias = Arc::clone(&ilias);
-		spawn(process_gracefully(ilias, path, item));
+		let dl = ilias.download(&url.url).await?;
+		spawn(handle_gracefully(path.clone(), async move {
+			log!(0, "Writing {}", name);
+			write_stream_to_file(&path, body_stream(dl))
+				.await
+				.context("failed to write exercise feedback image")
+		}));
 	}
 	Ok(())
 }
+
+/// Parse a date as displayed by ILIAS (German or English UI).
+pub(crate) fn parse_date(text: &str) -> Option<PrimitiveDateTime> {
+	let captures = DATE.captures(text)?;
+	let number = |i| captures.get(i).and_then(|x| x.as_str().parse::<u8>().ok());
+	let date = if let Some(relative) = captures.get(4) {
+		// the current date in Germany is (almost always) the same as in UTC
+		let today = OffsetDateTime::now_utc().date();
+		match &*relative.as_str().to_lowercase() {
+			"heute" | "today" => today,
+			"morgen" | "tomorrow" => today + Duration::days(1),
+			"gestern" | "yesterday" => today - Duration::days(1),
+			_ => return None,
+		}
+	} else {
+		let month = match &captures[2].to_lowercase()[..] {
+			"jan" => 1,
+			"feb" => 2,
+			"mär" | "mar" => 3,
+			"apr" => 4,
+			"mai" | "may" => 5,
+			"jun" => 6,
+			"jul" => 7,
+			"aug" => 8,
+			"sep" => 9,
+			"okt" | "oct" => 10,
+			"nov" => 11,
+			"dez" | "dec" => 12,
+			_ => return None,
+		};
+		let year = captures[3].parse().ok()?;
+		Date::from_calendar_date(year, Month::try_from(month).ok()?, number(1)?).ok()?
+	};
+	let time = Time::from_hms(number(5)?, number(6)?, 0).ok()?;
+	Some(PrimitiveDateTime::new(date, time))
+}
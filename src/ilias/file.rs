@@ -1,22 +1,396 @@
-use std::{path::Path, sync::Arc};
+use std::{
+	path::{Path, PathBuf},
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc,
+	},
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use scraper::Selector;
+use serde_json::json;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 use tokio::fs;
 
-use crate::util::write_stream_to_file;
+use crate::{
+	archive, process_gracefully,
+	queue::spawn,
+	stats,
+	util::{create_dir, file_escape, set_modified, write_file_data},
+};
 
-use super::{ILIAS, URL};
+use crate::cli::Opt;
 
-pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
-	if ilias.opt.skip_files {
+use super::{Object, ILIAS, URL};
+
+static TABLE_ROWS: Lazy<Selector> = Lazy::new(|| Selector::parse("tr").unwrap());
+static TABLE_CELLS: Lazy<Selector> = Lazy::new(|| Selector::parse("td").unwrap());
+static VERSION_LINK: Lazy<Selector> = Lazy::new(|| Selector::parse(r#"a[href*="hist_id="]"#).unwrap());
+static YEAR: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b\d{4}\b").unwrap());
+static INFO_PROPERTIES: Lazy<Selector> = Lazy::new(|| Selector::parse(".il_InfoScreenProperty, .form-group").unwrap());
+static MD5: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b[0-9a-fA-F]{32}\b").unwrap());
+
+/// Number of files skipped due to `--only-ext` / `--skip-ext`
+pub static FILTERED_FILES: AtomicUsize = AtomicUsize::new(0);
+
+pub async fn download(
+	path: &Path,
+	relative_path: &Path,
+	ilias: Arc<ILIAS>,
+	url: &URL,
+	version: Option<&str>,
+) -> Result<()> {
+	if is_skipped(path, &ilias.opt) {
+		return Ok(());
+	}
+	if is_filtered_by_extension(path, &ilias.opt) {
+		log!(1, "Skipping {} (file extension)", relative_path.to_string_lossy());
+		FILTERED_FILES.fetch_add(1, Ordering::SeqCst);
+		stats::SKIPPED.fetch_add(1, Ordering::SeqCst);
 		return Ok(());
 	}
-	if !ilias.opt.force && fs::metadata(&path).await.is_ok() {
-		log!(2, "Skipping download, file exists already");
+	// (the older versions themselves are downloaded using their hist_id)
+	if ilias.opt.all_file_versions && url.query_param("hist_id").is_none() {
+		if let Err(e) = download_versions(path, &ilias, url).await {
+			warning!(format => "could not list versions of {}: {:?}", relative_path.display(), e);
+		}
+	}
+	// with --strip-version-suffix, the version of the file on disk is stored in the state file
+	let parsed_version = version;
+	let version = version.filter(|_| ilias.opt.strip_version_suffix);
+	// ETag of the previous download, used to check whether the file changed
+	let mut etag = None;
+	if !ilias.opt.force && fs::metadata(&path).await.is_ok() && !ilias.is_incomplete(&url.url, path).await {
+		let previous = ilias.state.get(&url.url).unwrap_or_default();
+		match version {
+			Some(version) if previous.version.as_deref() != Some(version) => {
+				log!(1, "File was updated to version {}", version);
+			},
+			// ILIAS increases the version when a file is replaced,
+			// only files without version are checked for changes (one request per file)
+			_ => match previous.etag {
+				Some(previous) if parsed_version.is_none() && !ilias.opt.dry_run => etag = Some(previous),
+				_ => {
+					log!(2, "Skipping download, file exists already");
+					stats::SKIPPED.fetch_add(1, Ordering::SeqCst);
+					return write_missing_metadata(path, &ilias, url, parsed_version).await;
+				},
+			},
+		}
+	}
+	if ilias.opt.dry_run {
+		log!(0, "Would download {}", relative_path.to_string_lossy());
 		return Ok(());
 	}
-	let data = ilias.download(&url.url).await?;
-	log!(0, "Writing {}", relative_path.to_string_lossy());
-	write_stream_to_file(path, data.bytes_stream()).await?;
+	if etag.is_none() {
+		log!(0, "Writing {}", relative_path.to_string_lossy());
+	}
+	let download = match ilias
+		.download_to_file_if_changed(&url.url, path, etag.as_deref(), ilias.opt.max_file_size)
+		.await?
+	{
+		Some(download) => download,
+		None => {
+			log!(2, "Skipping download, file not modified or too large");
+			stats::SKIPPED.fetch_add(1, Ordering::SeqCst);
+			return write_missing_metadata(path, &ilias, url, parsed_version).await;
+		},
+	};
+	if ilias.opt.verify_checksums {
+		let md5 = download.md5.context("checksum of download missing")?;
+		verify_checksum(path, relative_path, &ilias, url, md5).await?;
+	}
+	if etag.is_some() {
+		log!(0, "Updated {}", relative_path.to_string_lossy());
+	}
+	if let Some(modified) = download.modified.filter(|_| !ilias.opt.no_set_mtime) {
+		set_modified(path, modified).await?;
+	}
+	archive::add_file(path).await?;
+	if let Some(version) = version {
+		ilias
+			.state
+			.update(&url.url, |entry| entry.version = Some(version.to_owned()))
+			.context("failed to save file version")?;
+	}
+	if ilias.opt.metadata_sidecars {
+		write_metadata(path, url, parsed_version).await?;
+	}
+	ilias.run_post_download_hook(path, "file", &url.url).await
+}
+
+/// Download all versions listed on the version page of the file into the `versions` folder next to it,
+/// named `v<version>_<date>_<file name>`.
+async fn download_versions(path: &Path, ilias: &Arc<ILIAS>, url: &URL) -> Result<()> {
+	let ref_id = url.object_ref_id().context("file without ref_id")?;
+	let versions = {
+		let html = ilias
+			.get_html(&format!(
+				"ilias.php?baseClass=ilRepositoryGUI&cmd=versions&cmdClass=ilobjfilegui&ref_id={}",
+				ref_id
+			))
+			.await?;
+		let mut versions = Vec::new();
+		for row in html.select(&TABLE_ROWS) {
+			let link = match row.select(&VERSION_LINK).next() {
+				Some(link) => link,
+				None => continue,
+			};
+			let cells = row
+				.select(&TABLE_CELLS)
+				.map(|x| x.text().collect::<String>().trim().to_owned())
+				.collect::<Vec<_>>();
+			let version = cells
+				.iter()
+				.find(|x| !x.is_empty() && x.chars().all(|c| c.is_ascii_digit()));
+			let date = cells.iter().find(|x| YEAR.is_match(x));
+			let name = link.text().collect::<String>().trim().to_owned();
+			let (version, date) = match (version, date) {
+				(Some(version), Some(date)) => (version, date),
+				_ => continue,
+			};
+			versions.push((
+				format!("v{}_{}_{}", version, date, name),
+				URL::from_href(link.value().attr("href").unwrap())?,
+			));
+		}
+		versions
+	};
+	if versions.is_empty() {
+		log!(1, "No versions found for {}", path.display());
+		return Ok(());
+	}
+	let dir = path.with_file_name("versions");
+	create_dir(&dir).await?;
+	for (name, url) in versions {
+		let name = file_escape(&name);
+		let item = Object::File {
+			url,
+			name: name.clone(),
+			version: None,
+		};
+		spawn(process_gracefully(Arc::clone(ilias), dir.join(name), item));
+	}
 	Ok(())
 }
+
+/// Compare the checksum of the downloaded file with the MD5 checksum shown on the info page of the file (if any).
+/// If they do not match, the file is downloaded once more.
+/// (The size of the file is always compared with the Content-Length of the download.)
+async fn verify_checksum(path: &Path, relative_path: &Path, ilias: &ILIAS, url: &URL, md5: String) -> Result<()> {
+	let expected = match file_checksum(ilias, url).await? {
+		Some(expected) => expected,
+		None => {
+			log!(2, "No checksum available for {}", relative_path.display());
+			return Ok(());
+		},
+	};
+	let mut actual = md5;
+	for attempt in 1..=2 {
+		if actual.eq_ignore_ascii_case(&expected) {
+			log!(2, "Checksum of {} matches", relative_path.display());
+			return Ok(());
+		}
+		if attempt == 2 {
+			break;
+		}
+		warning!(format => "checksum mismatch for {}, downloading again", relative_path.display());
+		let download = ilias
+			.download_to_file_if_changed(&url.url, path, None, ilias.opt.max_file_size)
+			.await?;
+		actual = match download.and_then(|x| x.md5) {
+			Some(md5) => md5,
+			// the file grew larger than --max-file-size in the meantime
+			None => break,
+		};
+	}
+	let _ = fs::remove_file(path).await;
+	Err(anyhow!("checksum mismatch for {}", relative_path.display()))
+}
+
+/// Find the MD5 checksum on the info page of the file.
+async fn file_checksum(ilias: &ILIAS, url: &URL) -> Result<Option<String>> {
+	let ref_id = match url.object_ref_id() {
+		Some(ref_id) => ref_id,
+		None => return Ok(None),
+	};
+	let html = ilias
+		.get_html(&format!(
+			"ilias.php?baseClass=ilRepositoryGUI&cmd=infoScreen&cmdClass=ilobjfilegui&ref_id={}",
+			ref_id
+		))
+		.await?;
+	let checksum = html
+		.select(&INFO_PROPERTIES)
+		.map(|x| x.text().collect::<String>())
+		.filter(|x| {
+			let x = x.to_lowercase();
+			x.contains("md5") || x.contains("checksum") || x.contains("prüfsumme")
+		})
+		.find_map(|x| MD5.find(&x).map(|x| x.as_str().to_owned()));
+	Ok(checksum)
+}
+
+/// Write `<file name>.meta.json` containing the ILIAS URL, the version and the download time.
+async fn write_metadata(path: &Path, url: &URL, version: Option<&str>) -> Result<()> {
+	let metadata = json!({
+		"url": url.url,
+		"version": version,
+		"downloaded": OffsetDateTime::now_utc().format(&Rfc3339)?,
+	});
+	write_file_data(metadata_path(path)?, &mut metadata.to_string().as_bytes())
+		.await
+		.context("failed to write metadata")
+}
+
+/// Write the metadata of a file that is not downloaded again, if it is missing
+/// (e.g. the file was downloaded before `--metadata-sidecars` was used).
+async fn write_missing_metadata(path: &Path, ilias: &ILIAS, url: &URL, version: Option<&str>) -> Result<()> {
+	if !ilias.opt.metadata_sidecars || ilias.opt.dry_run || fs::metadata(path).await.is_err() {
+		return Ok(());
+	}
+	if fs::metadata(metadata_path(path)?).await.is_err() {
+		write_metadata(path, url, version).await?;
+	}
+	Ok(())
+}
+
+fn metadata_path(path: &Path) -> Result<PathBuf> {
+	let mut name = path.file_name().context("file without name")?.to_owned();
+	name.push(".meta.json");
+	Ok(path.with_file_name(name))
+}
+
+/// Whether the file is not downloaded due to `--skip-files` (and not kept by `--keep-ext`).
+pub fn is_skipped(path: &Path, opt: &Opt) -> bool {
+	opt.skip_files && !has_extension(path, &opt.keep_ext)
+}
+
+fn is_filtered_by_extension(path: &Path, opt: &Opt) -> bool {
+	(!opt.only_ext.is_empty() && !has_extension(path, &opt.only_ext)) || has_extension(path, &opt.skip_ext)
+}
+
+fn has_extension(path: &Path, list: &[String]) -> bool {
+	let extension = path
+		.extension()
+		.map(|x| x.to_string_lossy().to_lowercase())
+		.unwrap_or_default();
+	list.iter()
+		.any(|x| x.trim_start_matches('.').eq_ignore_ascii_case(&extension))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_server::{self, Response};
+
+	const INFO_PAGE: &str = include_str!("../../tests/fixtures/file_info.html");
+
+	#[tokio::test]
+	async fn checksum_matches() {
+		test_server::serve("target=file_5001_download", Response::file(b"Vorlesung 1\n"));
+		test_server::serve("cmdClass=ilobjfilegui&ref_id=5001", Response::html(INFO_PAGE));
+		let (ilias, dir) = test_server::ilias(&["--verify-checksums"]).await;
+		let url = URL::from_href("goto.php?target=file_5001_download").unwrap();
+		let path = dir.path().join("Vorlesung 1.pdf");
+		download(&path, Path::new("Vorlesung 1.pdf"), Arc::new(ilias), &url, None)
+			.await
+			.unwrap();
+		assert_eq!(std::fs::read(&path).unwrap(), b"Vorlesung 1\n");
+		assert_eq!(test_server::requests("target=file_5001_download").len(), 1);
+	}
+
+	#[tokio::test]
+	async fn checksum_mismatch() {
+		test_server::serve("target=file_5002_download", Response::file(b"Vorlesung 2\n"));
+		test_server::serve("cmdClass=ilobjfilegui&ref_id=5002", Response::html(INFO_PAGE));
+		let (ilias, dir) = test_server::ilias(&["--verify-checksums"]).await;
+		let url = URL::from_href("goto.php?target=file_5002_download").unwrap();
+		let path = dir.path().join("Vorlesung 2.pdf");
+		let result = download(&path, Path::new("Vorlesung 2.pdf"), Arc::new(ilias), &url, None).await;
+		assert!(result.unwrap_err().to_string().contains("checksum mismatch"));
+		// downloaded once more before giving up
+		assert_eq!(test_server::requests("target=file_5002_download").len(), 2);
+		assert!(!path.exists());
+	}
+
+	#[tokio::test]
+	async fn stored_version_is_compared() {
+		test_server::serve("target=file_5004_download", Response::file(b"Blatt 4 (v3)\n"));
+		let (ilias, dir) = test_server::ilias(&["--strip-version-suffix"]).await;
+		let url = URL::from_href("goto.php?target=file_5004_download").unwrap();
+		let path = dir.path().join("Blatt 4.pdf");
+		std::fs::write(&path, b"Blatt 4 (v2)\n").unwrap();
+		ilias
+			.state
+			.update(&url.url, |x| x.version = Some("2".to_owned()))
+			.unwrap();
+		let ilias = Arc::new(ilias);
+
+		// same version: not downloaded again
+		download(&path, Path::new("Blatt 4.pdf"), Arc::clone(&ilias), &url, Some("2"))
+			.await
+			.unwrap();
+		assert!(test_server::requests("target=file_5004_download").is_empty());
+
+		download(&path, Path::new("Blatt 4.pdf"), Arc::clone(&ilias), &url, Some("3"))
+			.await
+			.unwrap();
+		assert_eq!(std::fs::read(&path).unwrap(), b"Blatt 4 (v3)\n");
+		assert_eq!(ilias.state.get(&url.url).unwrap().version.as_deref(), Some("3"));
+	}
+
+	#[tokio::test]
+	async fn unchanged_files_are_not_downloaded() {
+		test_server::serve(
+			"target=file_5005_download",
+			Response::file(b"").status(304).header("ETag", "\"5005\""),
+		);
+		let (ilias, dir) = test_server::ilias(&[]).await;
+		let url = URL::from_href("goto.php?target=file_5005_download").unwrap();
+		let path = dir.path().join("Blatt 5.pdf");
+		std::fs::write(&path, b"Blatt 5\n").unwrap();
+		ilias
+			.state
+			.update(&url.url, |x| x.etag = Some("\"5005\"".to_owned()))
+			.unwrap();
+		let ilias = Arc::new(ilias);
+
+		// the version shown by ILIAS did not change: no request
+		download(&path, Path::new("Blatt 5.pdf"), Arc::clone(&ilias), &url, Some("1"))
+			.await
+			.unwrap();
+		assert!(test_server::request_heads("target=file_5005_download").is_empty());
+
+		// unknown version: conditional request
+		download(&path, Path::new("Blatt 5.pdf"), Arc::clone(&ilias), &url, None)
+			.await
+			.unwrap();
+		let requests = test_server::request_heads("target=file_5005_download");
+		assert_eq!(requests.len(), 1);
+		assert!(requests[0].to_lowercase().contains("if-none-match: \"5005\""));
+		assert_eq!(std::fs::read(&path).unwrap(), b"Blatt 5\n");
+	}
+
+	#[tokio::test]
+	async fn missing_metadata_is_written() {
+		let (ilias, dir) = test_server::ilias(&["--metadata-sidecars", "--strip-version-suffix"]).await;
+		let url = URL::from_href("goto.php?target=file_5010_download").unwrap();
+		let path = dir.path().join("Blatt 10.pdf");
+		std::fs::write(&path, b"Blatt 10\n").unwrap();
+		ilias
+			.state
+			.update(&url.url, |x| x.version = Some("2".to_owned()))
+			.unwrap();
+
+		download(&path, Path::new("Blatt 10.pdf"), Arc::new(ilias), &url, Some("2"))
+			.await
+			.unwrap();
+		assert!(test_server::requests("target=file_5010_download").is_empty());
+		let metadata = std::fs::read_to_string(dir.path().join("Blatt 10.pdf.meta.json")).unwrap();
+		assert!(metadata.contains("target=file_5010_download"));
+		assert!(metadata.contains("\"version\":\"2\""));
+	}
+}
@@ -1,23 +1,33 @@
-use std::{collections::HashSet, path::Path, sync::Arc};
+use std::{
+	collections::HashSet,
+	path::{Path, PathBuf},
+	sync::{Arc, Mutex},
+};
 
 use anyhow::{Context, Result};
 use async_recursion::async_recursion;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use tokio::fs;
 
 use crate::{
 	process_gracefully,
 	queue::spawn,
-	util::{file_escape, write_file_data},
+	util::{file_escape, write_ilias_page},
 };
 
-use super::{ILIAS, URL};
+use super::{Object, ILIAS, URL};
 
 static EXPAND_LINK: Lazy<Regex> = Lazy::new(|| Regex::new("expand=\\d").unwrap());
+/// Paths used by `--flatten` (items of different folders end up in the same directory)
+static FLAT_PATHS: Lazy<Mutex<HashSet<PathBuf>>> = Lazy::new(Mutex::default);
 
 #[async_recursion]
 pub async fn download(path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
-	let content = ilias.get_course_content(url).await?;
+	let content = match ilias.take_tree_children(&url.ref_id) {
+		Some(items) => (items.into_iter().map(Result::Ok).collect(), None, Vec::new()),
+		None => ilias.get_course_content(url).await?,
+	};
 
 	// expand all sessions
 	for href in content.2 {
@@ -27,19 +37,43 @@ pub async fn download(path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
 		}
 	}
 
+	let mut items = content.0;
+	if flatten(path, &ilias, &mut items).await? {
+		return Ok(());
+	}
+
+	let flat = is_flat(path, &ilias);
 	if ilias.opt.save_ilias_pages {
 		if let Some(s) = content.1.as_ref() {
-			let path = path.join("folder.html");
-			write_file_data(&path, &mut s.as_bytes())
+			let path = if flat {
+				flat_path(path, "folder.html", false)
+			} else {
+				path.join("folder.html")
+			};
+			write_ilias_page(&path, s, &ilias.opt)
 				.await
 				.context("failed to write folder page html")?;
 		}
 	}
 
 	let mut names = HashSet::new();
-	for item in content.0 {
+	for item in items {
 		let item = item?;
-		let item_name = file_escape(ilias.course_names.get(item.name()).map(|x| &**x).unwrap_or(item.name()));
+		if !item.is_dir() && !ilias.is_recent(item.url()) {
+			log!(1, "Skipping {} (not changed recently)", item.name());
+			continue;
+		}
+		let item_name = file_escape(
+			&ilias
+				.course_names
+				.get(item.name())
+				.unwrap_or_else(|| item.file_name(&ilias.opt)),
+		);
+		if flat {
+			let path = flat_path(path, &item_name, item.is_dir());
+			spawn(process_gracefully(Arc::clone(&ilias), path, item));
+			continue;
+		}
 		if names.contains(&item_name) {
 			warning!(format => "folder {} contains duplicated folder {:?}", path.display(), item_name);
 		}
@@ -50,3 +84,68 @@ pub async fn download(path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
 	}
 	Ok(())
 }
+
+/// If `--flatten-single-child-folders` is active and the only item is a directory,
+/// that directory is processed as `<parent> — <child>` instead of nesting it.
+/// Returns true if the item was handled this way.
+pub async fn flatten(path: &Path, ilias: &Arc<ILIAS>, items: &mut Vec<Result<Object>>) -> Result<bool> {
+	if !ilias.opt.flatten_single_child_folders
+		|| path == ilias.opt.content_dir()
+		|| items.len() != 1
+		|| !matches!(&items[0], Ok(item) if item.is_dir())
+	{
+		return Ok(false);
+	}
+	// only possible if the parent directory is still empty (it may contain files from a previous sync)
+	let removed = if ilias.opt.dry_run {
+		// (a dry run does not create the directory and must not remove it)
+		match fs::read_dir(path).await {
+			Ok(mut entries) => matches!(entries.next_entry().await, Ok(None)),
+			Err(_) => true,
+		}
+	} else {
+		fs::remove_dir(path).await.is_ok()
+	};
+	if !removed {
+		return Ok(false);
+	}
+	let item = items.pop().unwrap()?;
+	let name = format!(
+		"{} — {}",
+		path.file_name().context("folder without name")?.to_string_lossy(),
+		file_escape(&item.file_name(&ilias.opt))
+	);
+	log!(1, "Flattening {} into {}", path.display(), name);
+	spawn(process_gracefully(Arc::clone(ilias), path.with_file_name(name), item));
+	Ok(true)
+}
+
+/// With `--flatten`, folders below the top level (e.g. inside courses) are not created as directories.
+/// Their items are saved next to them instead, see `flat_path`.
+pub fn is_flat(path: &Path, ilias: &ILIAS) -> bool {
+	let content_dir = ilias.opt.content_dir();
+	ilias.opt.flatten && path != content_dir && path.parent() != Some(&content_dir)
+}
+
+/// Path of an item in a flattened folder: `<folder> - <item>`.
+/// A number is appended to the name if that path is already used by another item.
+fn flat_path(folder: &Path, item_name: &str, is_dir: bool) -> PathBuf {
+	let folder_name = folder.file_name().map(|x| x.to_string_lossy()).unwrap_or_default();
+	let (name, extension) = match item_name.rsplit_once('.') {
+		Some((name, extension)) if !is_dir && !name.is_empty() => (name, Some(extension)),
+		_ => (item_name, None),
+	};
+	let mut paths = FLAT_PATHS.lock().unwrap();
+	for i in 1.. {
+		let suffix = if i == 1 { String::new() } else { i.to_string() };
+		let file_name = match extension {
+			Some(extension) => format!("{} - {}{}.{}", folder_name, name, suffix, extension),
+			None => format!("{} - {}{}", folder_name, name, suffix),
+		};
+		let path = folder.with_file_name(file_escape(&file_name));
+		if paths.insert(path.clone()) {
+			return path;
+		}
+	}
+	unreachable!()
+}
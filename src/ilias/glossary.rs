@@ -0,0 +1,118 @@
+use std::{
+	collections::{HashMap, HashSet},
+	path::Path,
+	sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use scraper::Selector;
+
+use crate::util::{escape_html, file_escape, wrap_html, write_html};
+
+use super::{ILIAS, URL};
+
+static LINKS: Lazy<Selector> = Lazy::new(|| Selector::parse("a").unwrap());
+static CONTENT: Lazy<Selector> = Lazy::new(|| Selector::parse("#il_center_col").unwrap());
+static TERM_PAGES: Lazy<Selector> =
+	Lazy::new(|| Selector::parse("div.ilTableNav > table > tbody > tr > td > a").unwrap());
+/// Links to other terms of a glossary (either directly or via goto.php)
+static TERM_LINK: Lazy<Regex> = Lazy::new(|| Regex::new(r#"href="[^"]*(?:term_id=|git_)(\d+)[^"]*""#).unwrap());
+
+pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
+	if !ilias.opt.glossary {
+		return Ok(());
+	}
+	let mut terms = Vec::new();
+	let mut seen = HashSet::new();
+	let mut pages = vec![url.url.clone()];
+	let mut visited_pages = HashSet::new();
+	while let Some(page) = pages.pop() {
+		if !visited_pages.insert(page.clone()) {
+			continue;
+		}
+		let html = ilias.get_html(&page).await?;
+		for link in html.select(&LINKS) {
+			let href = match link.value().attr("href") {
+				Some(href) if href.contains("term_id=") => href,
+				_ => continue,
+			};
+			let term_url = URL::from_href(href)?;
+			if term_url.cmd.as_deref() != Some("listDefinitions") || !seen.insert(term_url.url.clone()) {
+				continue;
+			}
+			let title = link.text().collect::<String>().trim().to_owned();
+			if !title.is_empty() {
+				let id = term_url.query_param("term_id").unwrap_or_default();
+				terms.push((id, title, term_url));
+			}
+		}
+		// long glossaries are split into multiple pages
+		for href in html.select(&TERM_PAGES).flat_map(|x| x.value().attr("href")) {
+			let href = URL::from_href(href)?.url;
+			if !visited_pages.contains(&href) {
+				pages.push(href);
+			}
+		}
+	}
+	// where the internal links should point to
+	let targets = terms
+		.iter()
+		.map(|(id, title, _)| {
+			let target = if ilias.opt.glossary_per_term {
+				file_escape(&format!("{}.html", title))
+			} else {
+				format!("#term-{}", id)
+			};
+			(id.clone(), target)
+		})
+		.collect::<HashMap<_, _>>();
+	let mut definitions = Vec::new();
+	for (id, title, term_url) in terms {
+		let definition = {
+			let html = ilias.get_html(&term_url.url).await?;
+			html.select(&CONTENT)
+				.next()
+				.context("glossary term definition not found")?
+				.inner_html()
+		};
+		let definition = TERM_LINK
+			.replace_all(&definition, |caps: &Captures| match targets.get(&caps[1]) {
+				Some(target) => format!("href=\"{}\"", escape_html(target)),
+				None => caps[0].to_owned(),
+			})
+			.into_owned();
+		if ilias.opt.glossary_per_term {
+			let name = file_escape(&format!("{}.html", title));
+			log!(0, "Writing {}", relative_path.join(&name).display());
+			write_html(path.join(&name), &wrap_html(&definition), ilias.opt.compress_html)
+				.await
+				.context("failed to write glossary term")?;
+		} else {
+			definitions.push((id, title, definition));
+		}
+	}
+	if ilias.opt.glossary_per_term {
+		return Ok(());
+	}
+
+	definitions.sort_by_cached_key(|(_, title, _)| title.to_lowercase());
+	let mut index = String::from("<ul>");
+	let mut body = String::new();
+	for (id, title, definition) in &definitions {
+		let title = escape_html(title);
+		index += &format!("<li><a href=\"#term-{}\">{}</a></li>", id, title);
+		body += &format!("<h2 id=\"term-{}\">{}</h2>{}", id, title, definition);
+	}
+	index += "</ul>";
+	log!(0, "Writing {}", relative_path.join("glossary.html").display());
+	write_html(
+		path.join("glossary.html"),
+		&wrap_html(&(index + &body)),
+		ilias.opt.compress_html,
+	)
+	.await
+	.context("failed to write glossary")?;
+	Ok(())
+}
@@ -0,0 +1,56 @@
+use std::{collections::HashSet, path::Path, sync::Arc};
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use scraper::Selector;
+
+use crate::{process_gracefully, queue::spawn, util::file_escape};
+
+use super::{Object, ILIAS, URL};
+
+static ITEMS: Lazy<Selector> = Lazy::new(|| Selector::parse("#il_center_col tr, #il_center_col .il-item").unwrap());
+static ITEM_TITLE: Lazy<Selector> = Lazy::new(|| Selector::parse("h3, h4, .il-item-title").unwrap());
+static DOWNLOAD_LINK: Lazy<Selector> = Lazy::new(|| Selector::parse(r#"a[href*="downloadItem"]"#).unwrap());
+static MEDIA_SOURCE: Lazy<Selector> = Lazy::new(|| Selector::parse("video[src], audio[src], source[src]").unwrap());
+
+pub async fn download(path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
+	let items = {
+		let html = ilias.get_html(&url.url).await?;
+		let mut items = Vec::new();
+		for item in html.select(&ITEMS) {
+			// prefer the download link, embedded players may use a smaller version of the file
+			let href = item
+				.select(&DOWNLOAD_LINK)
+				.next()
+				.and_then(|x| x.value().attr("href"))
+				.or_else(|| item.select(&MEDIA_SOURCE).next().and_then(|x| x.value().attr("src")));
+			let href = match href {
+				Some(href) => href,
+				None => continue,
+			};
+			let title = item
+				.select(&ITEM_TITLE)
+				.next()
+				.map(|x| x.text().collect::<String>().trim().to_owned())
+				.unwrap_or_default();
+			items.push((URL::from_href(href)?, title));
+		}
+		items
+	};
+	let mut names = HashSet::new();
+	for (url, title) in items {
+		let name = ilias.get_embedded_file_name(&url, title).await;
+		let name = file_escape(&name);
+		if !names.insert(name.clone()) {
+			log!(1, "Skipping duplicate media cast item {}", name);
+			continue;
+		}
+		let item = Object::File {
+			url,
+			name: name.clone(),
+			version: None,
+		};
+		spawn(process_gracefully(Arc::clone(&ilias), path.join(name), item));
+	}
+	Ok(())
+}
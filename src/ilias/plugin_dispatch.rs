@@ -5,8 +5,9 @@ use once_cell::sync::Lazy;
 use regex::Regex;
 use reqwest::Url;
 use scraper::{Html, Selector};
+use tokio::fs;
 
-use crate::{ilias::Object, process_gracefully, queue::spawn, util::file_escape, ILIAS_URL};
+use crate::{cli::ilias_url, ilias::Object, process_gracefully, queue::spawn, util::file_escape};
 
 use super::{ILIAS, URL};
 
@@ -14,6 +15,7 @@ static LINKS: Lazy<Selector> = Lazy::new(|| Selector::parse("a").unwrap());
 static A_TARGET_BLANK: Lazy<Selector> = Lazy::new(|| Selector::parse(r#"a[target="_blank"]"#).unwrap());
 static VIDEO_ROWS: Lazy<Selector> = Lazy::new(|| Selector::parse(".ilTableOuter > div > table > tbody > tr").unwrap());
 static TABLE_CELLS: Lazy<Selector> = Lazy::new(|| Selector::parse("td").unwrap());
+static DATE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b(\d{1,2})\.(\d{1,2})\.(\d{4})\b").unwrap());
 static LIST_URL: Lazy<Regex> = Lazy::new(|| {
 	Regex::new("ilias\\.php\\?baseClass=ilobjplugindispatchgui&cmdNode=.{9}&cmdClass=xoctEventGUI&ref_id=\\d+&async=true").unwrap()
 });
@@ -27,7 +29,7 @@ pub async fn download(path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
 	let full_url = {
 		let html = ilias.download(&url.url).await?.text().await?;
 		let list_url = LIST_URL.find(&html).context("failed to find xoct event link")?.as_str();
-		let full_list_url = format!("{}{}", ILIAS_URL, list_url);
+		let full_list_url = format!("{}{}", ilias_url(), list_url);
 
 		// first find the link to full video list
 		log!(1, "Loading {}", full_list_url);
@@ -42,7 +44,7 @@ pub async fn download(path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
 			.context("video list link not found")?
 	};
 	log!(1, "Rewriting {}", full_url);
-	let mut full_url = Url::parse(&format!("{}{}", ILIAS_URL, full_url))?;
+	let mut full_url = Url::parse(&format!("{}{}", ilias_url(), full_url))?;
 	let mut query_parameters = full_url
 		.query_pairs()
 		.map(|(x, y)| (x.into_owned(), y.into_owned()))
@@ -63,32 +65,93 @@ pub async fn download(path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
 	log!(1, "Loading {}", full_url);
 	let data = ilias.download(full_url.as_str()).await?;
 	let html = data.text().await?;
-	let html = Html::parse_fragment(&html);
-	for row in html.select(&VIDEO_ROWS) {
-		let link = row.select(&A_TARGET_BLANK).next();
-		if link.is_none() {
-			if !row.text().any(|x| x == NO_ENTRIES) {
-				warning!(format => "table row without link in {}", url.url);
-			}
-			continue;
-		}
-		let link = link.unwrap();
-		let mut cells = row.select(&TABLE_CELLS);
-		if let Some(title) = cells.nth(2) {
-			let title = title.text().collect::<String>();
-			let title = title.trim();
-			if title.starts_with("<div") {
+	let videos = {
+		let html = Html::parse_fragment(&html);
+		let mut videos = Vec::new();
+		for row in html.select(&VIDEO_ROWS) {
+			let link = row.select(&A_TARGET_BLANK).next();
+			if link.is_none() {
+				if !row.text().any(|x| x == NO_ENTRIES) {
+					warning!(format => "table row without link in {}", url.url);
+				}
 				continue;
 			}
-			let mut path = path.to_owned();
-			path.push(format!("{}.mp4", file_escape(title)));
-			log!(1, "Found video: {}", title);
-			let video = Object::Video {
-				url: URL::raw(link.value().attr("href").context("video link without href")?.to_owned()),
+			let link = link.unwrap();
+			let cells = row
+				.select(&TABLE_CELLS)
+				.map(|x| x.text().collect::<String>().trim().to_owned())
+				.collect::<Vec<_>>();
+			let title = match cells.get(2).map(|x| video_title(x)) {
+				Some(title) if !title.is_empty() => title,
+				_ => {
+					warning!(format => "video without title in {}", url.url);
+					continue;
+				},
 			};
-			let ilias = Arc::clone(&ilias);
-			spawn(process_gracefully(ilias, path, video));
+			// prefix the recording date, so the videos are sorted chronologically
+			let date = cells.iter().find_map(|x| DATE.captures(x));
+			let date = date.map(|x| format!("{}-{:0>2}-{:0>2}", &x[3], &x[2], &x[1]));
+			let href = link.value().attr("href").context("video link without href")?;
+			videos.push((title, date, href.to_owned()));
+		}
+		videos
+	};
+	for (title, date, href) in videos {
+		let old_path = path.join(file_escape(&format!("{}.mp4", title)));
+		let video_path = match date {
+			Some(date) => path.join(file_escape(&format!("{} - {}.mp4", date, title))),
+			None => old_path.clone(),
+		};
+		if !ilias.opt.dry_run {
+			keep_old_download(&old_path, &video_path).await?;
 		}
+		log!(1, "Found video: {}", title);
+		let video = Object::Video { url: URL::raw(href) };
+		let ilias = Arc::clone(&ilias);
+		spawn(process_gracefully(ilias, video_path, video));
 	}
 	Ok(())
 }
+
+/// Videos used to be named `<title>.mp4`: rename these downloads instead of downloading them again.
+async fn keep_old_download(old_path: &Path, path: &Path) -> Result<()> {
+	if old_path == path || fs::metadata(old_path).await.is_err() || fs::metadata(path).await.is_ok() {
+		return Ok(());
+	}
+	log!(0, "Renaming {} to {}", old_path.display(), path.display());
+	fs::rename(old_path, path)
+		.await
+		.context("failed to rename previously downloaded video")
+}
+
+/// Some installations put (escaped) markup into the title cell, only keep its text.
+fn video_title(cell: &str) -> String {
+	if cell.starts_with('<') {
+		let fragment = Html::parse_fragment(cell);
+		fragment.root_element().text().collect::<String>().trim().to_owned()
+	} else {
+		cell.to_owned()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn old_video_names_are_renamed() {
+		let dir = tempfile::tempdir().unwrap();
+		let old_path = dir.path().join("Vorlesung 1.mp4");
+		let path = dir.path().join("2024-10-14 - Vorlesung 1.mp4");
+		std::fs::write(&old_path, b"video").unwrap();
+		keep_old_download(&old_path, &path).await.unwrap();
+		assert!(!old_path.exists());
+		assert_eq!(std::fs::read(&path).unwrap(), b"video");
+
+		// existing downloads under the new name are not replaced
+		std::fs::write(&old_path, b"other video").unwrap();
+		keep_old_download(&old_path, &path).await.unwrap();
+		assert_eq!(std::fs::read(&old_path).unwrap(), b"other video");
+		assert_eq!(std::fs::read(&path).unwrap(), b"video");
+	}
+}
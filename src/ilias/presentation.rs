@@ -0,0 +1,121 @@
+use std::{
+	collections::{HashMap, HashSet},
+	path::Path,
+	sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use scraper::Selector;
+
+use crate::util::{body_stream, create_dir, file_escape, wrap_html, write_html, write_stream_to_file};
+
+use super::{ILIAS, URL};
+
+static LINKS: Lazy<Selector> = Lazy::new(|| Selector::parse("a").unwrap());
+static IMAGES: Lazy<Selector> = Lazy::new(|| Selector::parse("img").unwrap());
+static CONTENT: Lazy<Selector> = Lazy::new(|| Selector::parse("#il_center_col").unwrap());
+static NEXT_PAGE: Lazy<Selector> =
+	Lazy::new(|| Selector::parse(".ilc_page_rnav_RightNavigation a, a.ilc_page_rnavlink_RightNavigationLink").unwrap());
+
+/// Subdirectory for images and other media of the learning module
+const MEDIA_DIR: &str = "media";
+
+struct Page {
+	content: String,
+	/// Links in the page content and the page they point to
+	links: Vec<(String, String)>,
+	/// Sources of the images in the page content
+	images: Vec<String>,
+}
+
+pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
+	if !ilias.opt.learning_modules {
+		return Ok(());
+	}
+	// follow the "next page" links until the end of the learning module
+	let mut pages = Vec::new();
+	let mut page_files = HashMap::new();
+	let mut next = Some(URL::from_href(&url.url)?);
+	while let Some(url) = next.take() {
+		if let Some(obj_id) = url.query_param("obj_id") {
+			if page_files.contains_key(&obj_id) {
+				break;
+			}
+			page_files.insert(obj_id, format!("{:03}.html", pages.len() + 1));
+		}
+		let html = ilias.get_html(&url.url).await?;
+		let content = html.select(&CONTENT).next().context("learning module page not found")?;
+		let links = content
+			.select(&LINKS)
+			.flat_map(|x| x.value().attr("href"))
+			.flat_map(|href| {
+				URL::from_href(href)
+					.ok()
+					.and_then(|x| x.query_param("obj_id"))
+					.map(|obj_id| (href.to_owned(), obj_id))
+			})
+			.collect();
+		let images = content
+			.select(&IMAGES)
+			.flat_map(|x| x.value().attr("src"))
+			.filter(|x| x.contains("/data/"))
+			.map(|x| x.to_owned())
+			.collect();
+		pages.push(Page {
+			content: content.inner_html(),
+			links,
+			images,
+		});
+		next = html
+			.select(&NEXT_PAGE)
+			.flat_map(|x| x.value().attr("href"))
+			.next()
+			.map(URL::from_href)
+			.transpose()?;
+	}
+
+	let mut media = HashSet::new();
+	for (i, page) in pages.into_iter().enumerate() {
+		let mut content = page.content;
+		for (href, obj_id) in page.links {
+			if let Some(file) = page_files.get(&obj_id) {
+				content = content.replace(
+					&format!("href=\"{}\"", href.replace('&', "&amp;")),
+					&format!("href=\"{}\"", file),
+				);
+			}
+		}
+		for src in page.images {
+			// ./data/<client>/mobs/mm_<id>/<file name>
+			let mut parts = src.split('?').next().unwrap_or(&src).rsplit('/');
+			let file = parts.next().unwrap_or_default();
+			let name = file_escape(&format!("{}_{}", parts.next().unwrap_or_default(), file));
+			content = content.replace(
+				&format!("src=\"{}\"", src.replace('&', "&amp;")),
+				&format!("src=\"{}/{}\"", MEDIA_DIR, name),
+			);
+			if media.insert(name.clone()) {
+				if ilias.opt.dry_run {
+					log!(
+						0,
+						"Would download {}",
+						relative_path.join(MEDIA_DIR).join(&name).display()
+					);
+					continue;
+				}
+				create_dir(&path.join(MEDIA_DIR)).await?;
+				let dl = ilias.download(&URL::from_href(&src)?.url).await?;
+				write_stream_to_file(&path.join(MEDIA_DIR).join(&name), body_stream(dl))
+					.await
+					.context("failed to write learning module media")?;
+			}
+		}
+		let name = format!("{:03}.html", i + 1);
+		log!(0, "Writing {}", relative_path.join(&name).display());
+		write_html(path.join(&name), &wrap_html(&content), ilias.opt.compress_html)
+			.await
+			.context("failed to write learning module page")?;
+	}
+	Ok(())
+}
@@ -0,0 +1,56 @@
+use std::{collections::HashSet, path::Path, sync::Arc};
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use scraper::Selector;
+
+use crate::util::{escape_html, wrap_html, write_html};
+
+use super::{ILIAS, URL};
+
+static LINKS: Lazy<Selector> = Lazy::new(|| Selector::parse("a").unwrap());
+static CONTENT: Lazy<Selector> = Lazy::new(|| Selector::parse("#il_center_col").unwrap());
+
+pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
+	let questions = {
+		let html = ilias.get_html(&url.url).await?;
+		let mut seen = HashSet::new();
+		let mut questions = Vec::new();
+		for link in html.select(&LINKS) {
+			let href = match link.value().attr("href") {
+				Some(href) if href.contains("q_id=") => href,
+				_ => continue,
+			};
+			let question_url = URL::from_href(href)?;
+			if question_url.cmd.as_deref() != Some("preview") || !seen.insert(question_url.query_param("q_id")) {
+				continue;
+			}
+			let title = link.text().collect::<String>().trim().to_owned();
+			if !title.is_empty() {
+				questions.push((title, question_url));
+			}
+		}
+		questions
+	};
+	if questions.is_empty() {
+		// the question list is only shown if the pool is shared with us
+		log!(1, "No accessible questions in {}", relative_path.display());
+		return Ok(());
+	}
+	let mut body = String::new();
+	for (i, (title, question_url)) in questions.iter().enumerate() {
+		let question = {
+			let html = ilias.get_html(&question_url.url).await?;
+			html.select(&CONTENT)
+				.next()
+				.context("question preview not found")?
+				.inner_html()
+		};
+		body += &format!("<h2 id=\"question-{}\">{}</h2>{}", i + 1, escape_html(title), question);
+	}
+	log!(0, "Writing {}", relative_path.join("questions.html").display());
+	write_html(path.join("questions.html"), &wrap_html(&body), ilias.opt.compress_html)
+		.await
+		.context("failed to write question pool")?;
+	Ok(())
+}
@@ -0,0 +1,104 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use scraper::Selector;
+
+use crate::util::{wrap_html, write_html};
+
+use super::{ILIAS, URL};
+
+static LINKS: Lazy<Selector> = Lazy::new(|| Selector::parse("a").unwrap());
+static CONTENT: Lazy<Selector> = Lazy::new(|| Selector::parse("#il_center_col").unwrap());
+static INFO: Lazy<Selector> = Lazy::new(|| Selector::parse(".alert-info").unwrap());
+
+/// Info messages shown if the survey did not start yet (German / English UI)
+const NOT_STARTED: &[&str] = &[
+	"noch nicht gestartet",
+	"noch nicht begonnen",
+	"not started yet",
+	"not yet started",
+];
+
+pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
+	if !ilias.opt.survey {
+		return Ok(());
+	}
+	let (overview, evaluation, questions, info) = {
+		let html = ilias.get_html(&url.url).await?;
+		let overview = html
+			.select(&CONTENT)
+			.next()
+			.context("survey overview not found")?
+			.inner_html();
+		// results are only linked if we are allowed to view them
+		let evaluation = html
+			.select(&LINKS)
+			.flat_map(|x| x.value().attr("href"))
+			.find(|x| x.contains("cmd=evaluation") || x.to_ascii_lowercase().contains("ilsurveyevaluationgui"))
+			.map(URL::from_href)
+			.transpose()?;
+		let questions = html
+			.select(&LINKS)
+			.flat_map(|x| x.value().attr("href"))
+			.find(|x| x.contains("cmd=questions") || x.to_ascii_lowercase().contains("ilsurveyeditorgui"))
+			.map(URL::from_href)
+			.transpose()?;
+		let info = html.select(&INFO).next().map(|x| x.text().collect::<String>());
+		(overview, evaluation, questions, info)
+	};
+	if let Some(info) = info {
+		let info = info.trim();
+		if NOT_STARTED.iter().any(|x| info.to_lowercase().contains(x)) {
+			log!(1, "Survey {}: {}", relative_path.display(), info);
+			return Ok(());
+		}
+	}
+	let mut content = overview;
+	// question list with aggregated results, or at least the questions
+	if let Some(page) = evaluation.or(questions) {
+		let html = ilias.get_html(&page.url).await?;
+		if let Some(results) = html.select(&CONTENT).next() {
+			content += &results.inner_html();
+		}
+	}
+	log!(0, "Writing {}", relative_path.join("survey.html").display());
+	write_html(path.join("survey.html"), &wrap_html(&content), ilias.opt.compress_html)
+		.await
+		.context("failed to write survey")?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_server::{self, Response};
+
+	#[tokio::test]
+	async fn questions_are_saved_without_results() {
+		let questions = include_str!("../../tests/fixtures/survey_questions.html");
+		let overview = include_str!("../../tests/fixtures/survey_finished.html");
+		test_server::serve("ref_id=8001&cmd=questions", Response::html(questions));
+		test_server::serve("ref_id=8001&cmd=infoScreen", Response::html(overview));
+		let (ilias, dir) = test_server::ilias(&["--survey"]).await;
+		let url = URL::from_href("ilias.php?ref_id=8001&cmd=infoScreen&baseClass=ilobjsurveygui").unwrap();
+		download(dir.path(), Path::new("Evaluation"), Arc::new(ilias), &url)
+			.await
+			.unwrap();
+		let survey = std::fs::read_to_string(dir.path().join("survey.html")).unwrap();
+		assert!(survey.contains("Bitte bewerten Sie die Vorlesung"));
+		assert!(survey.contains("Wie bewerten Sie das Tempo der Vorlesung?"));
+	}
+
+	#[tokio::test]
+	async fn survey_not_started_is_skipped() {
+		let overview = include_str!("../../tests/fixtures/survey_not_started.html");
+		test_server::serve("ref_id=8002&cmd=infoScreen", Response::html(overview));
+		let (ilias, dir) = test_server::ilias(&["--survey"]).await;
+		let url = URL::from_href("ilias.php?ref_id=8002&cmd=infoScreen&baseClass=ilobjsurveygui").unwrap();
+		download(dir.path(), Path::new("Abschlussumfrage"), Arc::new(ilias), &url)
+			.await
+			.unwrap();
+		assert!(!dir.path().join("survey.html").exists());
+	}
+}
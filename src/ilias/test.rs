@@ -0,0 +1,75 @@
+use std::{collections::HashSet, path::Path, sync::Arc};
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use scraper::Selector;
+
+use crate::util::{wrap_html, write_html};
+
+use super::{ILIAS, URL};
+
+static LINKS: Lazy<Selector> = Lazy::new(|| Selector::parse("a").unwrap());
+static CONTENT: Lazy<Selector> = Lazy::new(|| Selector::parse("#il_center_col").unwrap());
+static INFO: Lazy<Selector> = Lazy::new(|| Selector::parse(".alert-info").unwrap());
+
+pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
+	if !ilias.opt.tests {
+		return Ok(());
+	}
+	let (results, info) = {
+		let html = ilias.get_html(&url.url).await?;
+		// results are only linked once we finished a pass (and the test allows reviewing them)
+		let results = html
+			.select(&LINKS)
+			.flat_map(|x| x.value().attr("href"))
+			.find(|x| {
+				let x = x.to_ascii_lowercase();
+				x.contains("cmd=outuserresultsoverview") || x.contains("ilmytestresultsgui")
+			})
+			.map(URL::from_href)
+			.transpose()?;
+		let info = html.select(&INFO).next().map(|x| x.text().collect::<String>());
+		(results, info)
+	};
+	let results = match results {
+		Some(results) => results,
+		None => {
+			log!(
+				1,
+				"Test {}: {}",
+				relative_path.display(),
+				info.as_deref().map(str::trim).unwrap_or("no results available")
+			);
+			return Ok(());
+		},
+	};
+	let (overview, passes) = {
+		let html = ilias.get_html(&results.url).await?;
+		let overview = html
+			.select(&CONTENT)
+			.next()
+			.context("test results not found")?
+			.inner_html();
+		let mut seen = HashSet::new();
+		let mut passes = Vec::new();
+		for href in html.select(&LINKS).flat_map(|x| x.value().attr("href")) {
+			if href.contains("cmd=outUserPassDetails") && seen.insert(href) {
+				passes.push(URL::from_href(href)?);
+			}
+		}
+		(overview, passes)
+	};
+	let mut content = overview;
+	for (i, pass) in passes.iter().enumerate() {
+		let html = ilias.get_html(&pass.url).await?;
+		// every question with the given answer and the best solution (if enabled)
+		if let Some(details) = html.select(&CONTENT).next() {
+			content += &format!("<h2 id=\"pass-{}\">Pass {}</h2>{}", i + 1, i + 1, details.inner_html());
+		}
+	}
+	log!(0, "Writing {}", relative_path.join("test.html").display());
+	write_html(path.join("test.html"), &wrap_html(&content), ilias.opt.compress_html)
+		.await
+		.context("failed to write test results")?;
+	Ok(())
+}
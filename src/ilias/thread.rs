@@ -1,14 +1,25 @@
-use std::{path::Path, sync::Arc};
+use std::{
+	collections::HashSet,
+	fs::File,
+	io::{BufWriter, Write},
+	path::{Path, PathBuf},
+	sync::Arc,
+};
 
 use anyhow::{anyhow, Context, Result};
 use once_cell::sync::Lazy;
 use regex::Regex;
 use scraper::Selector;
+use tokio::sync::{Mutex, Semaphore};
+use zip::{ZipArchive, ZipWriter};
 
 use crate::{
-	handle_gracefully, process_gracefully,
+	cli::ForumFormat,
+	handle_gracefully,
+	markdown::to_markdown,
+	process_gracefully,
 	queue::spawn,
-	util::{file_escape, wrap_html, write_file_data},
+	util::{absolute_url, body_stream, file_escape, write_html, write_stream_to_file},
 };
 
 use super::{Object, ILIAS, URL};
@@ -24,12 +35,25 @@ static POST_ATTACHMENTS: Lazy<Selector> = Lazy::new(|| Selector::parse(".ilFrmPo
 static SPAN_SMALL: Lazy<Selector> = Lazy::new(|| Selector::parse("span.small").unwrap());
 static IMAGE_SRC_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\./data/produktiv/mobs/mm_(\d+)/([^?]+).+"#).unwrap());
 
+/// Maximum number of images / attachments of one thread page downloaded at the same time
+const PARALLEL_ATTACHMENTS: usize = 4;
+
+/// Held while the attachments of a page are added to the zip file of the thread (`--forum-attachments-zip`),
+/// the pages of a thread are processed concurrently.
+static THREAD_ZIP: Lazy<Mutex<()>> = Lazy::new(Mutex::default);
+
 pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
 	if !ilias.opt.forum {
 		return Ok(());
 	}
+	if ilias.opt.dry_run {
+		log!(0, "Would download {}", relative_path.display());
+		return Ok(());
+	}
 	let mut all_images = Vec::new();
 	let mut attachments = Vec::new();
+	// zip files with the attachments of a post (`--forum-attachments-zip`)
+	let mut post_zips = Vec::new();
 	{
 		let html = ilias.get_html(&url.url).await?;
 		for post in html.select(&POST_ROW) {
@@ -60,24 +84,54 @@ pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url:
 				.context("post container not found")?;
 			let link = container.select(&LINKS).next().context("post link not found")?;
 			let id = link.value().attr("id").context("no id in thread link")?.to_owned();
-			let name = format!("{}_{}_{}.html", id, author, title.trim());
-			let data = wrap_html(&container.inner_html());
+			let markdown = ilias.opt.forum_format == ForumFormat::Markdown;
+			let extension = if markdown { "md" } else { "html" };
+			let name = format!("{}_{}_{}.{}", id, author, title.trim(), extension);
+			// images are saved next to the post, the saved post refers to these copies
+			let mut images = Vec::new();
+			for image in container.select(&IMAGES) {
+				let src = image.value().attr("src").context("no src on image")?.to_owned();
+				images.push((image_file_name(&id, &src), src));
+			}
+			let data = if markdown {
+				let mut data = format!("# {}\n\n{}\n\n{}", title.trim(), author, to_markdown(container));
+				for (file_name, src) in &images {
+					data = data.replace(
+						&format!("]({})", absolute_url(src)),
+						&format!("]({})", local_url(file_name)),
+					);
+				}
+				data
+			} else {
+				let mut html = container.inner_html();
+				for (file_name, src) in &images {
+					html = html.replace(
+						&format!("src=\"{}\"", attribute_value(src)),
+						&format!("src=\"{}\"", local_url(file_name)),
+					);
+				}
+				// (no base URL, it would apply to the images too)
+				for href in container.select(&LINKS).flat_map(|x| x.value().attr("href")) {
+					html = html.replace(
+						&format!("href=\"{}\"", attribute_value(href)),
+						&format!("href=\"{}\"", attribute_value(&absolute_url(href))),
+					);
+				}
+				format!("<!DOCTYPE html>\n{}", html)
+			};
 			let path = path.join(file_escape(&name));
 			let relative_path = relative_path.join(file_escape(&name));
-			spawn(handle_gracefully(async move {
+			let compress = ilias.opt.compress_html && !markdown;
+			spawn(handle_gracefully(path.clone(), async move {
 				log!(0, "Writing {}", relative_path.display());
-				write_file_data(&path, &mut data.as_bytes())
+				write_html(&path, &data, compress)
 					.await
 					.context("failed to write forum post")
 			}));
-			let images = container
-				.select(&IMAGES)
-				.map(|x| x.value().attr("src").map(|x| x.to_owned()));
-			for image in images {
-				let image = image.context("no src on image")?;
-				all_images.push((id.clone(), image));
-			}
+			all_images.extend(images);
 			if let Some(container) = container.select(&POST_ATTACHMENTS).next() {
+				let mut post_attachments = Vec::new();
+				let mut zip = None;
 				for attachment in container.select(&LINKS) {
 					let href = attachment
 						.value()
@@ -85,9 +139,14 @@ pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url:
 						.map(|x| x.to_owned())
 						.context("attachment link without href")?;
 					if href.contains("cmd=deliverZipFile") {
+						zip = Some(href);
 						continue; // skip downloading all attachments as zip
 					}
-					attachments.push((id.clone(), attachment.text().collect::<String>(), href));
+					post_attachments.push((id.clone(), attachment.text().collect::<String>(), href));
+				}
+				match zip {
+					Some(href) if ilias.opt.forum_attachments_zip => post_zips.push((id.clone(), href)),
+					_ => attachments.extend(post_attachments),
 				}
 			}
 		}
@@ -113,42 +172,188 @@ pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url:
 			}
 		}
 	}
-	for (id, image) in all_images {
+	// the responses are only requested once a ticket is available,
+	// so threads with many attachments do not keep lots of connections open
+	let tickets = Arc::new(Semaphore::new(PARALLEL_ATTACHMENTS));
+	for (file_name, image) in all_images {
 		let src = URL::from_href(&image)?;
+		let ticket = Arc::clone(&tickets).acquire_owned().await?;
 		let dl = ilias.download(&src.url).await?;
 		let mut path = path.to_owned();
-		let file_name = if let Some(m) = IMAGE_SRC_REGEX.captures(&image) {
-			// image uploaded to ILIAS
-			let (media_id, filename) = (m.get(1).unwrap().as_str(), m.get(2).unwrap().as_str());
-			file_escape(&format!("{}_{}_{}", id, media_id, filename))
-		} else {
-			// external image
-			file_escape(&format!("{}_{}", id, image))
-		};
 		path.push(&file_name);
 		let relative_path = relative_path.join(file_name);
-		spawn(handle_gracefully(async move {
-			let bytes = dl.bytes().await?;
+		spawn(handle_gracefully(path.clone(), async move {
+			let _ticket = ticket;
 			log!(0, "Writing {}", relative_path.display());
-			write_file_data(&path, &mut &*bytes)
+			write_stream_to_file(&path, body_stream(dl))
 				.await
 				.context("failed to write forum post image attachment")
 		}));
 	}
 	for (id, name, url) in attachments {
 		let src = URL::from_href(&url)?;
+		let ticket = Arc::clone(&tickets).acquire_owned().await?;
 		let dl = ilias.download(&src.url).await?;
 		let mut path = path.to_owned();
 		let file_name = file_escape(&format!("{}_{}", id, name));
 		path.push(&file_name);
 		let relative_path = relative_path.join(file_name);
-		spawn(handle_gracefully(async move {
-			let bytes = dl.bytes().await?;
+		spawn(handle_gracefully(path.clone(), async move {
+			let _ticket = ticket;
 			log!(0, "Writing {}", relative_path.display());
-			write_file_data(&path, &mut &*bytes)
+			write_stream_to_file(&path, body_stream(dl))
 				.await
 				.context("failed to write forum post file attachment")
 		}));
 	}
+	if !post_zips.is_empty() {
+		download_attachment_zips(path, relative_path, &ilias, post_zips).await?;
+	}
 	Ok(())
 }
+
+/// Download the zip files with the attachments of the posts and add their files to `<thread>_attachments.zip`,
+/// in a folder named after the post id.
+/// ILIAS only offers these zip files per post. Posts already contained in the zip file are not downloaded again.
+async fn download_attachment_zips(
+	path: &Path,
+	relative_path: &Path,
+	ilias: &ILIAS,
+	post_zips: Vec<(String, String)>,
+) -> Result<()> {
+	let name = format!(
+		"{}_attachments.zip",
+		path.file_name().context("thread without name")?.to_string_lossy()
+	);
+	let name = file_escape(&name);
+	let zip_path = path.join(&name);
+	let saved_posts = {
+		let zip_path = zip_path.clone();
+		tokio::task::spawn_blocking(move || saved_posts(&zip_path)).await??
+	};
+	let dir = tempfile::tempdir()?;
+	let mut downloaded = Vec::new();
+	for (id, url) in post_zips {
+		if saved_posts.contains(&id) {
+			continue;
+		}
+		let src = URL::from_href(&url)?;
+		let dl = ilias.download(&src.url).await?;
+		let post_zip = dir.path().join(format!("{}.zip", id));
+		write_stream_to_file(&post_zip, body_stream(dl))
+			.await
+			.context("failed to download forum post attachments")?;
+		downloaded.push((id, post_zip));
+	}
+	if downloaded.is_empty() {
+		return Ok(());
+	}
+	log!(0, "Writing {}", relative_path.join(name).display());
+	let _lock = THREAD_ZIP.lock().await;
+	tokio::task::spawn_blocking(move || add_post_zips(&zip_path, &downloaded))
+		.await?
+		.context("failed to write forum attachments zip")
+}
+
+/// Ids of the posts whose attachments are contained in the zip file.
+fn saved_posts(zip_path: &Path) -> Result<HashSet<String>> {
+	let file = match File::open(zip_path) {
+		Ok(file) => file,
+		Err(_) => return Ok(HashSet::new()),
+	};
+	let zip = ZipArchive::new(file)?;
+	let posts = zip
+		.file_names()
+		.filter_map(|x| x.split_once('/'))
+		.map(|x| x.0.to_owned())
+		.collect();
+	Ok(posts)
+}
+
+/// Copy the files of the post zip files into the zip file of the thread.
+fn add_post_zips(zip_path: &Path, post_zips: &[(String, PathBuf)]) -> Result<()> {
+	let mut tmp = zip_path.as_os_str().to_owned();
+	tmp.push(".tmp");
+	let mut zip = ZipWriter::new(BufWriter::new(File::create(&tmp)?));
+	// (attachments of the other pages of the thread and of previous syncs)
+	if let Ok(file) = File::open(zip_path) {
+		let mut existing = ZipArchive::new(file)?;
+		for i in 0..existing.len() {
+			zip.raw_copy_file(existing.by_index_raw(i)?)?;
+		}
+	}
+	for (id, post_zip) in post_zips {
+		let mut post_zip = ZipArchive::new(File::open(post_zip)?)?;
+		for i in 0..post_zip.len() {
+			let file = post_zip.by_index_raw(i)?;
+			let name = format!("{}/{}", id, file.name());
+			zip.raw_copy_file_rename(file, name)?;
+		}
+	}
+	zip.finish()?.flush()?;
+	std::fs::rename(&tmp, zip_path)?;
+	Ok(())
+}
+
+/// File name of an image in the post with the specified id.
+pub(crate) fn image_file_name(id: &str, image: &str) -> String {
+	if let Some(m) = IMAGE_SRC_REGEX.captures(image) {
+		// image uploaded to ILIAS
+		let (media_id, filename) = (m.get(1).unwrap().as_str(), m.get(2).unwrap().as_str());
+		file_escape(&format!("{}_{}_{}", id, media_id, filename))
+	} else {
+		// external image
+		file_escape(&format!("{}_{}", id, image))
+	}
+}
+
+/// Relative URL of a file in the same directory.
+pub(crate) fn local_url(file_name: &str) -> String {
+	file_name
+		.replace('%', "%25")
+		.replace(' ', "%20")
+		.replace('#', "%23")
+		.replace('?', "%3F")
+		.replace('"', "%22")
+}
+
+/// Escapes the value like the HTML serializer does in attributes.
+pub(crate) fn attribute_value(value: &str) -> String {
+	value
+		.replace('&', "&amp;")
+		.replace('\u{a0}', "&nbsp;")
+		.replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn post_zip(path: &Path, files: &[&str]) -> PathBuf {
+		let mut zip = ZipWriter::new(File::create(path).unwrap());
+		for name in files {
+			zip.start_file(*name, Default::default()).unwrap();
+			zip.write_all(name.as_bytes()).unwrap();
+		}
+		zip.finish().unwrap();
+		path.to_owned()
+	}
+
+	#[test]
+	fn post_zips_are_combined() {
+		let dir = tempfile::tempdir().unwrap();
+		let zip_path = dir.path().join("42_Klausur_attachments.zip");
+		let first = post_zip(&dir.path().join("1.zip"), &["Lösung.pdf", "Skizze.png"]);
+		add_post_zips(&zip_path, &[("post_1".to_owned(), first)]).unwrap();
+		// posts of another page of the thread
+		let second = post_zip(&dir.path().join("2.zip"), &["Lösung.pdf"]);
+		add_post_zips(&zip_path, &[("post_2".to_owned(), second)]).unwrap();
+
+		let zip = ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+		let mut names = zip.file_names().collect::<Vec<_>>();
+		names.sort_unstable();
+		assert_eq!(names, ["post_1/Lösung.pdf", "post_1/Skizze.png", "post_2/Lösung.pdf"]);
+		let posts = saved_posts(&zip_path).unwrap();
+		assert_eq!(posts, HashSet::from(["post_1".to_owned(), "post_2".to_owned()]));
+	}
+}
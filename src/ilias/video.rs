@@ -1,7 +1,7 @@
 use std::{
 	path::{Path, PathBuf},
 	process::Stdio,
-	sync::Arc,
+	sync::{atomic::Ordering, Arc},
 };
 
 use anyhow::{Context, Result};
@@ -10,7 +10,12 @@ use regex::Regex;
 use tempfile::tempdir;
 use tokio::{fs, process::Command};
 
-use crate::{util::write_stream_to_file, ILIAS_URL};
+use crate::{
+	archive,
+	cli::{ilias_url, VideoQuality},
+	stats,
+	util::{body_stream, create_dir, file_escape, is_partial_download, set_modified, write_stream_to_file},
+};
 
 use super::{ILIAS, URL};
 
@@ -21,12 +26,20 @@ pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url:
 	if ilias.opt.no_videos {
 		return Ok(());
 	}
-	if fs::metadata(&path).await.is_ok() && !(ilias.opt.force || ilias.opt.check_videos) {
+	if fs::metadata(&path).await.is_ok()
+		&& !(ilias.opt.force || ilias.opt.check_videos)
+		&& !is_partial_download(path).await
+	{
 		log!(2, "Skipping download, file exists already");
+		stats::SKIPPED.fetch_add(1, Ordering::SeqCst);
+		return Ok(());
+	}
+	if ilias.opt.dry_run {
+		log!(0, "Would download {}", relative_path.to_string_lossy());
 		return Ok(());
 	}
-	let url = format!("{}{}", ILIAS_URL, url.url);
-	let data = ilias.download(&url);
+	let url = format!("{}{}", ilias_url(), url.url);
+	let data = ilias.download_file(&url);
 	let html = data.await?.text().await?;
 	log!(2, "{}", html);
 	let json: serde_json::Value = {
@@ -37,36 +50,22 @@ pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url:
 		serde_json::from_str(json.trim())?
 	};
 	log!(2, "{}", json);
+	download_captions(&ilias, path, relative_path, &json).await?;
 	let streams = json
 		.get("streams")
 		.context("video streams not found")?
 		.as_array()
 		.context("video streams not an array")?;
 	if streams.len() == 1 {
-		let url = streams[0]
-			.pointer("/sources/mp4/0/src")
-			.context("video src not found")?
-			.as_str()
-			.context("video src not string")?;
-		download_to_path(&ilias, path, relative_path, url).await?;
+		let url = select_source(&streams[0], ilias.opt.video_quality)?;
+		download_to_path(&ilias, path, relative_path, url, true).await?;
 	} else if !ilias.opt.combine_videos {
-		fs::create_dir(path).await.context("failed to create video directory")?;
-		download_all(path, streams, ilias, relative_path).await?;
+		create_dir(path).await.context("failed to create video directory")?;
+		download_all(path, streams, ilias, relative_path, true).await?;
 	} else {
 		let dir = tempdir()?;
-		// construct ffmpeg command to combine all files
-		let mut arguments = vec![];
-		for file in download_all(dir.path(), streams, ilias, relative_path).await? {
-			arguments.push("-i".to_owned());
-			arguments.push(file.to_str().context("invalid UTF8")?.into());
-		}
-		arguments.push("-c".into());
-		arguments.push("copy".into());
-		for i in 0..(arguments.len() / 2) - 1 {
-			arguments.push("-map".into());
-			arguments.push(format!("{}", i));
-		}
-		arguments.push(path.to_str().context("invalid UTF8 in path")?.into());
+		let files = download_all(dir.path(), streams, Arc::clone(&ilias), relative_path, false).await?;
+		let arguments = ffmpeg_arguments(&files, path)?;
 		let status = Command::new("ffmpeg")
 			.args(&arguments)
 			.stderr(Stdio::null())
@@ -80,30 +79,50 @@ pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url:
 			error!(format!("ffmpeg failed to merge video files into {}", path.display()));
 			error!(format!("check this directory: {}", dir.into_path().display()));
 			error!(format!("ffmpeg command: {}", arguments.join(" ")));
+		} else {
+			ilias.run_post_download_hook(path, "video", &url).await?;
 		}
 	}
 	Ok(())
 }
 
+/// Construct the ffmpeg arguments to combine all files into one video (with multiple video tracks).
+fn ffmpeg_arguments(files: &[PathBuf], output: &Path) -> Result<Vec<String>> {
+	let mut arguments = vec![];
+	for file in files {
+		arguments.push("-i".to_owned());
+		arguments.push(file.to_str().context("invalid UTF8")?.into());
+	}
+	// map the video stream of every input, and the audio streams (if present)
+	for i in 0..files.len() {
+		arguments.push("-map".into());
+		arguments.push(format!("{}:v:0", i));
+		arguments.push("-map".into());
+		arguments.push(format!("{}:a?", i));
+	}
+	arguments.push("-c".into());
+	arguments.push("copy".into());
+	arguments.push(output.to_str().context("invalid UTF8 in path")?.into());
+	Ok(arguments)
+}
+
 async fn download_all(
 	path: &Path,
 	streams: &[serde_json::Value],
 	ilias: Arc<ILIAS>,
 	relative_path: &Path,
+	run_hook: bool,
 ) -> Result<Vec<PathBuf>> {
 	let mut paths = Vec::new();
 	for (i, stream) in streams.iter().enumerate() {
-		let url = stream
-			.pointer("/sources/mp4/0/src")
-			.context("video src not found")?
-			.as_str()
-			.context("video src not string")?;
+		let url = select_source(stream, ilias.opt.video_quality)?;
 		let new_path = path.join(format!("Stream{}.mp4", i + 1));
 		download_to_path(
 			&ilias,
 			&new_path,
 			&relative_path.join(format!("Stream{}.mp4", i + 1)),
 			url,
+			run_hook,
 		)
 		.await?;
 		paths.push(new_path);
@@ -111,22 +130,143 @@ async fn download_all(
 	Ok(paths)
 }
 
-async fn download_to_path(ilias: &ILIAS, path: &Path, relative_path: &Path, url: &str) -> Result<()> {
+/// Download the caption tracks (if any) next to the video as `<video name>.<lang>.vtt`.
+async fn download_captions(ilias: &ILIAS, path: &Path, relative_path: &Path, json: &serde_json::Value) -> Result<()> {
+	let captions = match json
+		.get("captions")
+		.or_else(|| json.get("subtitles"))
+		.and_then(|x| x.as_array())
+	{
+		Some(captions) => captions,
+		None => return Ok(()),
+	};
+	for caption in captions {
+		let url = caption.get("url").or_else(|| caption.get("src"));
+		let url = match url.and_then(|x| x.as_str()) {
+			Some(url) => url,
+			None => continue,
+		};
+		let lang = caption
+			.get("lang")
+			.and_then(|x| x.as_str())
+			.filter(|x| !x.is_empty())
+			.unwrap_or("unknown");
+		let extension = format!("{}.vtt", file_escape(lang));
+		let path = path.with_extension(&extension);
+		if !ilias.opt.force && fs::metadata(&path).await.is_ok() {
+			continue;
+		}
+		let resp = ilias.download_file(url).await?;
+		let relative_path = relative_path.with_extension(&extension);
+		log!(0, "Writing {}", relative_path.to_string_lossy());
+		write_stream_to_file(&path, body_stream(resp)).await?;
+	}
+	Ok(())
+}
+
+/// Select the MP4 source of the stream matching the `--video-quality` best.
+fn select_source(stream: &serde_json::Value, quality: VideoQuality) -> Result<&str> {
+	let sources = stream
+		.pointer("/sources/mp4")
+		.context("video src not found")?
+		.as_array()
+		.context("video sources not an array")?;
+	let height = |source: &serde_json::Value| {
+		source
+			.pointer("/res/h")
+			.or_else(|| source.get("height"))
+			.and_then(|x| x.as_u64().or_else(|| x.as_str().and_then(|x| x.parse().ok())))
+			.unwrap_or(0)
+	};
+	let source = match quality {
+		VideoQuality::Highest => sources.iter().max_by_key(|x| height(x)),
+		VideoQuality::Lowest => sources.iter().min_by_key(|x| height(x)),
+		VideoQuality::Height(h) => {
+			let source = sources.iter().min_by_key(|x| (height(x) as i64 - h as i64).abs());
+			if let Some(source) = source.filter(|x| height(x) != h as u64) {
+				log!(0, "Video resolution {}p not available, using {}p", h, height(source));
+			}
+			source
+		},
+	}
+	.context("video src not found")?;
+	source
+		.get("src")
+		.context("video src not found")?
+		.as_str()
+		.context("video src not string")
+}
+
+async fn download_to_path(ilias: &ILIAS, path: &Path, relative_path: &Path, url: &str, run_hook: bool) -> Result<()> {
 	let meta = fs::metadata(&path).await;
-	if !ilias.opt.force && meta.is_ok() && ilias.opt.check_videos {
+	if !ilias.opt.force && meta.is_ok() && ilias.opt.check_videos && !ilias.is_incomplete(url, path).await {
 		let head = ilias.head(url).await.context("HEAD request failed")?;
-		if let Some(len) = head.headers().get("content-length") {
-			if meta?.len() != len.to_str()?.parse::<u64>()? {
-				warning!(
-					relative_path.to_string_lossy(),
-					"was updated, consider moving the outdated file"
-				);
-			}
+		// compare with the state of the previous download, if known
+		let state = ilias.state.get(url).unwrap_or_default();
+		let etag = head.headers().get("etag").and_then(|x| x.to_str().ok());
+		let updated = match (etag, state.etag.as_deref()) {
+			(Some(etag), Some(previous)) => etag != previous,
+			_ => match head.headers().get("content-length") {
+				Some(len) => state.size.unwrap_or(meta?.len()) != len.to_str()?.parse::<u64>()?,
+				None => false,
+			},
+		};
+		if updated {
+			warning!(
+				relative_path.to_string_lossy(),
+				"was updated, consider moving the outdated file"
+			);
 		}
 	} else {
-		let resp = ilias.download(url).await?;
 		log!(0, "Writing {}", relative_path.to_string_lossy());
-		write_stream_to_file(path, resp.bytes_stream()).await?;
+		let modified = ilias.download_to_file(url, path).await?;
+		if let Some(modified) = modified.filter(|_| !ilias.opt.no_set_mtime) {
+			set_modified(path, modified).await?;
+		}
+		archive::add_file(path).await?;
+		if run_hook {
+			ilias.run_post_download_hook(path, "video", url).await?;
+		}
 	}
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn arguments(inputs: &[&str]) -> Vec<String> {
+		let files = inputs.iter().map(PathBuf::from).collect::<Vec<_>>();
+		ffmpeg_arguments(&files, Path::new("out.mp4")).unwrap()
+	}
+
+	#[test]
+	fn ffmpeg_one_input() {
+		assert_eq!(
+			arguments(&["a.mp4"]),
+			["-i", "a.mp4", "-map", "0:v:0", "-map", "0:a?", "-c", "copy", "out.mp4"]
+		);
+	}
+
+	#[test]
+	fn ffmpeg_two_inputs() {
+		assert_eq!(
+			arguments(&["a.mp4", "b.mp4"]),
+			[
+				"-i", "a.mp4", "-i", "b.mp4", "-map", "0:v:0", "-map", "0:a?", "-map", "1:v:0", "-map", "1:a?", "-c",
+				"copy", "out.mp4"
+			]
+		);
+	}
+
+	#[test]
+	fn ffmpeg_three_inputs() {
+		assert_eq!(
+			arguments(&["a.mp4", "b.mp4", "c.mp4"]),
+			[
+				"-i", "a.mp4", "-i", "b.mp4", "-i", "c.mp4", "-map", "0:v:0", "-map", "0:a?", "-map", "1:v:0", "-map",
+				"1:a?", "-map", "2:v:0", "-map", "2:a?", "-c", "copy", "out.mp4"
+			]
+		);
+	}
+}
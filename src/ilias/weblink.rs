@@ -1,4 +1,7 @@
-use std::{path::Path, sync::Arc};
+use std::{
+	path::{Path, PathBuf},
+	sync::Arc,
+};
 
 use anyhow::{Context, Result};
 use once_cell::sync::Lazy;
@@ -6,8 +9,8 @@ use scraper::Selector;
 use tokio::fs;
 
 use crate::{
-	util::{create_dir, file_escape, write_file_data},
-	ILIAS_URL,
+	cli::{ilias_url, WeblinkFormat},
+	util::{create_dir, escape_html, file_escape, write_file_data},
 };
 
 use super::{ILIAS, URL};
@@ -15,7 +18,10 @@ use super::{ILIAS, URL};
 static LINKS: Lazy<Selector> = Lazy::new(|| Selector::parse("a").unwrap());
 
 pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
-	if !ilias.opt.force && fs::metadata(&path).await.is_ok() {
+	let format = ilias.opt.weblink_format;
+	if !ilias.opt.force
+		&& (fs::metadata(&path).await.is_ok() || fs::metadata(shortcut_path(path, format)).await.is_ok())
+	{
 		log!(2, "Skipping download, link exists already");
 		return Ok(());
 	}
@@ -24,7 +30,7 @@ pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url:
 		Err(e) => e.url().context("HEAD request failed")?.as_str(),
 		Ok(head) => head.url().as_str(),
 	};
-	if url.starts_with(ILIAS_URL) {
+	if url.starts_with(ilias_url()) {
 		// is a link list
 		if fs::metadata(&path).await.is_err() {
 			create_dir(path).await?;
@@ -58,13 +64,54 @@ pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url:
 			}
 			let head = head.unwrap();
 			let url = head.url().as_str();
-			write_file_data(path.join(file_escape(&name)), &mut url.as_bytes()).await?;
+			let path = shortcut_path(&path.join(file_escape(&name)), format);
+			write_file_data(path, &mut shortcut(&name, url, format).as_bytes()).await?;
 		}
 	} else {
 		log!(0, "Writing {}", relative_path.to_string_lossy());
-		write_file_data(&path, &mut url.as_bytes())
+		let name = path.file_name().context("weblink without name")?.to_string_lossy();
+		let data = shortcut(&name, url, format);
+		write_file_data(shortcut_path(path, format), &mut data.as_bytes())
 			.await
 			.context("failed to save weblink URL")?;
 	}
 	Ok(())
 }
+
+/// Path of the link file, with the extension of the format.
+fn shortcut_path(path: &Path, format: WeblinkFormat) -> PathBuf {
+	let extension = match format {
+		WeblinkFormat::Txt => return path.to_owned(),
+		WeblinkFormat::Url => "url",
+		WeblinkFormat::Webloc => "webloc",
+		WeblinkFormat::Desktop => "desktop",
+	};
+	let mut name = path.file_name().unwrap_or_default().to_owned();
+	name.push(".");
+	name.push(extension);
+	path.with_file_name(name)
+}
+
+/// Content of the link file in the specified format.
+fn shortcut(name: &str, url: &str, format: WeblinkFormat) -> String {
+	match format {
+		WeblinkFormat::Txt => url.to_owned(),
+		// Windows Internet Shortcut
+		WeblinkFormat::Url => format!("[InternetShortcut]\r\nURL={}\r\n", url),
+		// macOS
+		WeblinkFormat::Webloc => format!(
+			concat!(
+				"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+				"<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n",
+				"<plist version=\"1.0\">\n<dict>\n\t<key>URL</key>\n\t<string>{}</string>\n</dict>\n</plist>\n"
+			),
+			escape_html(url)
+		),
+		// freedesktop.org desktop entry (Linux)
+		WeblinkFormat::Desktop => format!(
+			"[Desktop Entry]\nType=Link\nName={}\nURL={}\n",
+			name.replace('\n', " "),
+			url
+		),
+	}
+}
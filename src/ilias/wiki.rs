@@ -0,0 +1,100 @@
+use std::{
+	collections::{HashMap, HashSet},
+	path::Path,
+	sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use scraper::Selector;
+
+use crate::util::{file_escape, wrap_html, write_html};
+
+use super::{ILIAS, URL};
+
+static LINKS: Lazy<Selector> = Lazy::new(|| Selector::parse("a").unwrap());
+static CONTENT: Lazy<Selector> = Lazy::new(|| Selector::parse("#il_center_col").unwrap());
+
+pub async fn download(path: &Path, relative_path: &Path, ilias: Arc<ILIAS>, url: &URL) -> Result<()> {
+	if !ilias.opt.wiki {
+		return Ok(());
+	}
+	// the start page links to the list of all pages
+	let all_pages = {
+		let html = ilias.get_html(&url.url).await?;
+		html.select(&LINKS)
+			.flat_map(|x| x.value().attr("href"))
+			.find(|x| x.contains("cmd=allPages"))
+			.map(URL::from_href)
+			.context("wiki page list not found")??
+	};
+	let pages = {
+		let html = ilias.get_html(&all_pages.url).await?;
+		let mut seen = HashSet::new();
+		let mut pages = Vec::new();
+		for link in html.select(&CONTENT).flat_map(|x| x.select(&LINKS)) {
+			let page_url = match link.value().attr("href").map(URL::from_href) {
+				Some(Ok(url)) => url,
+				_ => continue,
+			};
+			if let Some(title) = page_title(&page_url) {
+				if seen.insert(title.clone()) {
+					pages.push((title, page_url));
+				}
+			}
+		}
+		pages
+	};
+	// local file of each page, used to rewrite links between the pages
+	let files = pages
+		.iter()
+		.map(|(title, _)| (title.clone(), file_escape(&format!("{}.html", title))))
+		.collect::<HashMap<_, _>>();
+	for (title, page_url) in &pages {
+		let mut content = {
+			let html = ilias.get_html(&page_url.url).await?;
+			let content = html.select(&CONTENT).next().context("wiki page content not found")?;
+			let mut links = Vec::new();
+			for link in content.select(&LINKS) {
+				let href = match link.value().attr("href") {
+					Some(href) => href,
+					None => continue,
+				};
+				let file = URL::from_href(href)
+					.ok()
+					.and_then(|x| page_title(&x))
+					.and_then(|x| files.get(&x));
+				if let Some(file) = file {
+					links.push((href.replace('&', "&amp;"), file));
+				}
+			}
+			let mut content = content.inner_html();
+			for (href, file) in links {
+				content = content.replace(&format!("href=\"{}\"", href), &format!("href=\"{}\"", file));
+			}
+			content
+		};
+		content = wrap_html(&content);
+		let name = &files[title];
+		log!(0, "Writing {}", relative_path.join(name).display());
+		write_html(path.join(name), &content, ilias.opt.compress_html)
+			.await
+			.context("failed to write wiki page")?;
+	}
+	Ok(())
+}
+
+/// Returns the title of the wiki page the URL points to.
+fn page_title(url: &URL) -> Option<String> {
+	if url.is_goto() {
+		// goto.php?target=wiki_<ref_id>_<title>
+		// (goto.php?target=wiki_wpage_<page_id>_<ref_id> links do not contain the title)
+		let target = url.query_param("target")?;
+		if target.starts_with("wiki_wpage_") {
+			return None;
+		}
+		let (_, title) = target.strip_prefix("wiki_")?.split_once('_')?;
+		return Some(title.replace('_', " "));
+	}
+	url.query_param("page").filter(|x| !x.is_empty())
+}
@@ -1,6 +1,8 @@
 use std::{
+	collections::HashMap,
 	ffi::OsString,
 	path::{Component, Path, PathBuf},
+	sync::{Arc, Mutex},
 };
 
 use anyhow::Result;
@@ -9,10 +11,17 @@ use ignore::gitignore::Gitignore;
 #[derive(Clone, Debug)]
 pub struct IliasIgnore {
 	ignores: Vec<IgnoreFile>,
+	/// .iliasinclude file in the output directory (if not empty)
+	include: Option<Arc<IncludeFile>>,
+	/// Directory the synced content is saved to
+	root: PathBuf,
+	/// .iliasignore files found in subdirectories (e.g. inside a course)
+	nested: Arc<Mutex<HashMap<PathBuf, Option<Arc<Gitignore>>>>>,
 }
 
 impl IliasIgnore {
-	pub fn load(mut path: PathBuf) -> Result<Self> {
+	pub fn load(mut path: PathBuf, root: PathBuf) -> Result<Self> {
+		let include = IncludeFile::load(&path.join(".iliasinclude")).map(Arc::new);
 		let mut ignores = Vec::new();
 		let mut prefix = Vec::new();
 		// example scenario:
@@ -42,10 +51,34 @@ impl IliasIgnore {
 			}
 			path.pop();
 		}
-		Ok(IliasIgnore { ignores })
+		Ok(IliasIgnore {
+			ignores,
+			include,
+			root,
+			nested: Default::default(),
+		})
 	}
 
 	pub fn should_ignore(&self, path: &Path, is_dir: bool) -> bool {
+		if let Some(include) = self.include.as_ref() {
+			if !include.is_included(path, is_dir) {
+				return true;
+			}
+		}
+		// the deepest .iliasignore file takes precedence
+		for dir in path.ancestors().skip(1) {
+			if dir.as_os_str().is_empty() {
+				break; // .iliasignore files in the root directory are already loaded
+			}
+			if let Some(ignore) = self.nested_ignore(dir) {
+				let matched = ignore.matched(self.root.join(path), is_dir);
+				if matched.is_whitelist() {
+					return false;
+				} else if matched.is_ignore() {
+					return true;
+				}
+			}
+		}
 		for ignore_file in &self.ignores {
 			let mut full_path = ignore_file.prefix.clone();
 			full_path.push(path.as_os_str());
@@ -58,6 +91,25 @@ impl IliasIgnore {
 		}
 		false
 	}
+
+	/// Load the .iliasignore file in the specified directory (relative to the root directory), if any.
+	fn nested_ignore(&self, dir: &Path) -> Option<Arc<Gitignore>> {
+		let mut nested = self.nested.lock().unwrap();
+		nested
+			.entry(dir.to_owned())
+			.or_insert_with(|| {
+				let (ignore, error) = Gitignore::new(self.root.join(dir).join(".iliasignore"));
+				if let Some(err) = error {
+					warning!(err);
+				}
+				if ignore.is_empty() {
+					None
+				} else {
+					Some(Arc::new(ignore))
+				}
+			})
+			.clone()
+	}
 }
 
 #[derive(Clone, Debug)]
@@ -65,3 +117,62 @@ struct IgnoreFile {
 	ignore: Gitignore,
 	prefix: OsString,
 }
+
+/// Paths listed in an .iliasinclude file (same syntax as .iliasignore): everything else is ignored.
+#[derive(Debug)]
+struct IncludeFile {
+	include: Gitignore,
+	/// Directories leading to the included paths (these have to be traversed)
+	prefixes: Vec<PathBuf>,
+	/// Some pattern may match at any depth, so all directories have to be traversed
+	unanchored: bool,
+}
+
+impl IncludeFile {
+	fn load(path: &Path) -> Option<Self> {
+		let (include, error) = Gitignore::new(path);
+		if let Some(err) = error {
+			warning!(err);
+		}
+		if include.is_empty() {
+			return None;
+		}
+		let mut prefixes = Vec::new();
+		let mut unanchored = false;
+		for line in std::fs::read_to_string(path).unwrap_or_default().lines() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+				continue;
+			}
+			let pattern = line.trim_end_matches('/');
+			// patterns without a slash (or starting with **) match in any directory
+			if !pattern.contains('/') || pattern.starts_with("**") {
+				unanchored = true;
+				continue;
+			}
+			prefixes.push(
+				pattern
+					.trim_start_matches('/')
+					.split('/')
+					.take_while(|x| !x.contains(&['*', '?', '['][..]))
+					.collect(),
+			);
+		}
+		Some(IncludeFile {
+			include,
+			prefixes,
+			unanchored,
+		})
+	}
+
+	fn is_included(&self, path: &Path, is_dir: bool) -> bool {
+		// "ignored" = listed in the file, "whitelisted" = excluded using !pattern
+		let matched = self.include.matched_path_or_any_parents(path, is_dir);
+		if matched.is_ignore() {
+			return true;
+		} else if matched.is_whitelist() {
+			return false;
+		}
+		is_dir && (self.unanchored || self.prefixes.iter().any(|x| x.starts_with(path)))
+	}
+}
@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::{duplicates::format_size, util::escape_html};
+
+const INDEX_FILE: &str = "index.html";
+/// Overview pages saved by `--save-ilias-pages`
+const OVERVIEW_PAGES: &[&str] = &["course.html", "course.html.gz", "folder.html", "folder.html.gz"];
+
+/// Write an `index.html` listing the subdirectories and files into every directory below `root` (`--generate-index`).
+/// Files starting with a dot (session, ignore files, ..) are not listed.
+pub fn write_indexes(root: &Path) -> Result<()> {
+	write_index(root, root)
+}
+
+fn write_index(root: &Path, dir: &Path) -> Result<()> {
+	let mut dirs = Vec::new();
+	let mut files = Vec::new();
+	for entry in fs::read_dir(dir).with_context(|| format!("failed to list {}", dir.display()))? {
+		let entry = entry?;
+		let name = entry.file_name().to_string_lossy().into_owned();
+		if name.starts_with('.') || name.ends_with(".part") || name == INDEX_FILE {
+			continue;
+		}
+		if entry.file_type()?.is_dir() {
+			write_index(root, &entry.path())?;
+			dirs.push(name);
+		} else {
+			files.push((name, entry.metadata()?.len()));
+		}
+	}
+	dirs.sort();
+	files.sort();
+
+	let title = if dir == root {
+		"ILIAS".to_owned()
+	} else {
+		dir.strip_prefix(root).unwrap().to_string_lossy().into_owned()
+	};
+	let mut html = format!(
+		"<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body>\n<h1>{0}</h1>\n",
+		escape_html(&title)
+	);
+	if dir != root {
+		html += "<p><a href=\"../index.html\">..</a></p>\n";
+	}
+	for (name, _) in files.iter().filter(|(name, _)| OVERVIEW_PAGES.contains(&name.as_str())) {
+		html += &format!("<p><a href=\"{}\">Overview page</a></p>\n", encode_href(name));
+	}
+	html += "<ul>\n";
+	for name in &dirs {
+		html += &format!(
+			"<li><a href=\"{}/index.html\">{}/</a></li>\n",
+			encode_href(name),
+			escape_html(name)
+		);
+	}
+	for (name, len) in &files {
+		html += &format!(
+			"<li><a href=\"{}\">{}</a> ({})</li>\n",
+			encode_href(name),
+			escape_html(name),
+			format_size(*len)
+		);
+	}
+	html += "</ul>\n</body>\n</html>\n";
+	fs::write(dir.join(INDEX_FILE), html).with_context(|| format!("failed to write index of {}", dir.display()))
+}
+
+/// Percent-encode a file name for use in a relative link.
+fn encode_href(name: &str) -> String {
+	let mut href = String::new();
+	for byte in name.bytes() {
+		if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+			href.push(byte as char);
+		} else {
+			href += &format!("%{:02X}", byte);
+		}
+	}
+	href
+}
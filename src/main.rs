@@ -4,80 +4,133 @@ use anyhow::{anyhow, Context, Result};
 use futures::future::{self, Either};
 use futures::StreamExt;
 use indicatif::{ProgressDrawTarget, ProgressStyle};
+use once_cell::sync::Lazy;
 use structopt::StructOpt;
 use tokio::fs;
 
-use std::collections::HashMap;
+use std::ffi::OsString;
 use std::future::Future;
 use std::io::BufReader;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
-use std::sync::Arc;
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
-static ILIAS_URL: &str = "https://ilias.studium.kit.edu/";
-static DEFAULT_SYNC_URL: &str =
-	"https://ilias.studium.kit.edu/ilias.php?baseClass=ilDashboardGUI&cmd=jumpToMemberships";
+/// Objects found by `--list`: relative path and description
+static LIST: Lazy<Mutex<Vec<(PathBuf, String)>>> = Lazy::new(Mutex::default);
+/// Relative to `--base-url`
+static DEFAULT_SYNC_URL: &str = "ilias.php?baseClass=ilDashboardGUI&cmd=jumpToMemberships";
 
+mod archive;
+mod bagit;
 #[macro_use]
 mod cli;
 use cli::*;
+mod course_names;
+use course_names::CourseNames;
+mod duplicates;
 mod ilias;
 use ilias::*;
+mod ical;
 mod iliasignore;
+mod index;
+mod markdown;
 use iliasignore::*;
 use Object::*;
 mod queue;
+mod state;
+mod stats;
+#[cfg(test)]
+mod test_server;
+mod timing;
 mod util;
 use util::*;
 
 #[tokio::main]
 async fn main() {
-	let opt = Opt::from_args();
-	if let Err(e) = real_main(opt).await {
+	let args = std::env::args_os().collect::<Vec<_>>();
+	let opt = Opt::from_iter(&args);
+	if let Err(e) = real_main(opt, &args).await {
 		error!(e);
+		std::process::exit(1);
 	}
 }
 
-async fn try_to_load_session(opt: Opt, ignore: IliasIgnore, course_names: HashMap<String, String>) -> Result<ILIAS> {
+async fn try_to_load_session(opt: Opt, ignore: IliasIgnore, course_names: CourseNames) -> Result<ILIAS> {
 	let session_path = opt.output.join(".iliassession");
 	let meta = tokio::fs::metadata(&session_path).await?;
 	let modified = meta.modified()?;
 	let now = SystemTime::now();
-	// the previous session is only useful if it isn't older than ~1 hour
+	// the previous session is only useful if it isn't older than --session-max-age (~1 hour by default)
 	let duration = now.duration_since(modified)?;
-	if duration.as_secs() <= 60 * 60 {
-		let file = std::fs::File::open(session_path)?;
-		let cookies = cookie_store::CookieStore::load_json(BufReader::new(file))
-			.map_err(|err| anyhow!(err))
-			.context("failed to load session cookies")?;
-		let cookie_store = reqwest_cookie_store::CookieStoreMutex::new(cookies);
-		let cookie_store = std::sync::Arc::new(cookie_store);
-		Ok(ILIAS::with_session(opt, cookie_store, ignore, course_names).await?)
-	} else {
-		Err(anyhow!("session data too old"))
+	log!(1, "Previous session is {} minutes old", duration.as_secs() / 60);
+	if duration.as_secs() > opt.session_max_age * 60 {
+		return Err(anyhow!("session data too old"));
 	}
+	let cookies = match read_cookies(&session_path) {
+		Ok(cookies) => cookies,
+		// the file exists, but is not valid JSON (e.g. the program was killed while writing it)
+		Err(e) if e.downcast_ref::<std::io::Error>().is_none() => {
+			let _ = fs::remove_file(&session_path).await;
+			return Err(e.context("session file is corrupted, removed it"));
+		},
+		Err(e) => return Err(e),
+	};
+	with_cookies(opt, cookies, ignore, course_names).await
 }
 
-async fn login(opt: Opt, ignore: IliasIgnore, course_names: HashMap<String, String>) -> Result<ILIAS> {
-	// load .iliassession file
-	if opt.keep_session {
-		match try_to_load_session(opt.clone(), ignore.clone(), course_names.clone())
-			.await
-			.context("failed to load previous session")
-		{
-			Ok(ilias) => {
-				info!("Checking session validity..");
-				// TODO: this probably isn't the best solution..
-				if let Err(e) = ilias.get_html(DEFAULT_SYNC_URL).await {
-					error!(e);
-				} else {
-					success!("Session still active!");
-					return Ok(ilias);
-				}
-			},
-			Err(e) => warning!(e),
-		}
+async fn load_cookies(opt: Opt, path: &Path, ignore: IliasIgnore, course_names: CourseNames) -> Result<ILIAS> {
+	with_cookies(opt, read_cookies(path)?, ignore, course_names).await
+}
+
+fn read_cookies(path: &Path) -> Result<cookie_store::CookieStore> {
+	let file = std::fs::File::open(path)?;
+	cookie_store::CookieStore::load_json(BufReader::new(file))
+		.map_err(|err| anyhow!(err))
+		.context("failed to load session cookies")
+}
+
+async fn with_cookies(
+	opt: Opt,
+	cookies: cookie_store::CookieStore,
+	ignore: IliasIgnore,
+	course_names: CourseNames,
+) -> Result<ILIAS> {
+	let cookie_store = reqwest_cookie_store::CookieStoreMutex::new(cookies);
+	let cookie_store = std::sync::Arc::new(cookie_store);
+	ILIAS::with_session(opt, cookie_store, ignore, course_names).await
+}
+
+async fn login(opt: Opt, ignore: IliasIgnore, course_names: CourseNames) -> Result<ILIAS> {
+	// load cookies specified by the user or the .iliassession file
+	let previous_session = if let Some(path) = opt.load_cookies.as_ref() {
+		Some(
+			load_cookies(opt.clone(), path, ignore.clone(), course_names.clone())
+				.await
+				.context("failed to load cookies"),
+		)
+	} else if opt.keep_session {
+		Some(
+			try_to_load_session(opt.clone(), ignore.clone(), course_names.clone())
+				.await
+				.context("failed to load previous session"),
+		)
+	} else {
+		None
+	};
+	match previous_session {
+		Some(Ok(ilias)) => {
+			info!("Checking session validity..");
+			// TODO: this probably isn't the best solution..
+			if let Err(e) = ilias.get_html(DEFAULT_SYNC_URL).await {
+				error!(e);
+			} else {
+				success!("Session still active!");
+				return Ok(ilias);
+			}
+		},
+		Some(Err(e)) => warning!(e),
+		None => {},
 	}
 
 	// load .iliaslogin file
@@ -104,40 +157,83 @@ async fn login(opt: Opt, ignore: IliasIgnore, course_names: HashMap<String, Stri
 	Ok(ilias)
 }
 
-async fn real_main(mut opt: Opt) -> Result<()> {
+async fn real_main(opt: Opt, args: &[OsString]) -> Result<()> {
+	let start = Instant::now();
+	let mut opt = opt.load_config(args)?;
 	LOG_LEVEL.store(opt.verbose, Ordering::SeqCst);
+	if !opt.base_url.ends_with('/') {
+		opt.base_url.push('/');
+	}
+	let _ = BASE_URL.set(opt.base_url.clone());
+	if let Some(path) = opt.log_file.as_ref() {
+		let file = std::fs::OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(path)
+			.context("failed to open log file")?;
+		let _ = LOG_FILE.set(std::sync::Mutex::new(file));
+	}
+	MAX_NAME_LENGTH.store(opt.max_name_length, Ordering::SeqCst);
 	#[cfg(windows)]
 	let _ = colored::control::set_virtual_terminal(true);
+	if let Some(mode) = opt.chmod {
+		if cfg!(unix) {
+			FILE_MODE.store(mode, Ordering::SeqCst);
+		} else {
+			warning!(format => "--chmod is only supported on Unix, ignoring it");
+		}
+	}
+
+	if opt.structure_only || opt.list {
+		// only the directory tree is wanted
+		opt.save_ilias_pages = false;
+	}
+	if opt.list {
+		// not even the output directory is created
+		opt.dry_run = true;
+		DRY_RUN.store(true, Ordering::SeqCst);
+	}
 
 	create_dir(&opt.output)
 		.await
 		.context("failed to create output directory")?;
 	// use UNC paths on Windows (to avoid the default max. path length of 255)
-	opt.output = fs::canonicalize(opt.output)
+	opt.output = match fs::canonicalize(&opt.output).await {
+		Ok(path) => path,
+		Err(_) if opt.list => opt.output.clone(),
+		Err(e) => return Err(e).context("failed to canonicalize output directory"),
+	};
+
+	create_dir(&opt.content_dir())
 		.await
-		.context("failed to canonicalize output directory")?;
+		.context("failed to create output directory")?;
+	// (only the output directory is created)
+	DRY_RUN.store(opt.dry_run, Ordering::SeqCst);
 
 	// load .iliasignore file
-	let ignore = IliasIgnore::load(opt.output.clone())?;
+	let ignore = IliasIgnore::load(opt.output.clone(), opt.content_dir())?;
 
 	// Load course_names.toml file
 	let course_names_path = opt.output.join("course_names.toml");
 	let course_names = if fs::metadata(&course_names_path).await.is_ok() {
 		// file exists, try to read it
-		toml::from_str(
-			&fs::read_to_string(course_names_path)
-				.await
-				.context("accessing course_names.toml")?,
-		)
-		.context("processing course_names.toml")?
+		CourseNames::load(&course_names_path)?
 	} else {
-		// If file doesn't exist, initialise course_names with empty HashMap
-		HashMap::new()
+		CourseNames::default()
 	};
 
-	queue::set_download_rate(opt.rate);
+	queue::set_download_rate(opt.rate, opt.rate_burst, opt.jitter);
+	if let Some(max_bandwidth) = opt.max_bandwidth {
+		queue::set_max_bandwidth(max_bandwidth);
+	}
+	if let Some(jobs) = opt.per_host_jobs {
+		queue::set_per_host_jobs(jobs);
+	}
 
 	let ilias = login(opt, ignore, course_names).await?;
+	if let Some(path) = ilias.opt.dump_cookies.as_ref() {
+		ilias.save_cookies(path).context("failed to dump cookies")?;
+	}
 
 	if ilias.opt.content_tree {
 		if let Err(e) = ilias
@@ -148,10 +244,15 @@ async fn real_main(mut opt: Opt) -> Result<()> {
 		}
 	}
 	let ilias = Arc::new(ilias);
+	if let Some(minutes) = ilias.opt.refresh_session {
+		tokio::spawn(refresh_session(Arc::clone(&ilias), Duration::from_secs(minutes * 60)));
+	}
 	let mut rx = queue::set_parallel_jobs(ilias.opt.jobs);
+	tokio::spawn(handle_ctrl_c());
 	PROGRESS_BAR_ENABLED.store(atty::is(atty::Stream::Stdout), Ordering::SeqCst);
 	if PROGRESS_BAR_ENABLED.load(Ordering::SeqCst) {
-		PROGRESS_BAR.set_draw_target(ProgressDrawTarget::stderr());
+		MULTI_PROGRESS.set_draw_target(ProgressDrawTarget::stderr());
+		MULTI_PROGRESS.add(PROGRESS_BAR.clone());
 		PROGRESS_BAR.set_style(ProgressStyle::default_bar().template("[{pos}/{len}+] {wide_msg}")?);
 		PROGRESS_BAR.set_message("initializing..");
 	}
@@ -159,18 +260,33 @@ async fn real_main(mut opt: Opt) -> Result<()> {
 	let sync_url = if ilias.opt.all {
 		format!(
 			"{}ilias.php?cmdClass=ilmembershipoverviewgui&baseClass=ilmembershipoverviewgui",
-			ILIAS_URL
+			ilias_url()
 		)
 	} else {
 		ilias.opt.sync_url.as_deref().unwrap_or(DEFAULT_SYNC_URL).to_owned()
 	};
-	let obj = Object::from_url(
-		URL::from_href(&sync_url).context("invalid sync URL")?,
-		String::new(),
-		None,
-	)
-	.context("invalid sync object")?;
-	queue::spawn(process_gracefully(ilias.clone(), ilias.opt.output.clone(), obj));
+	let obj = if let Some(ref_id) = ilias.opt.repo_ref_id {
+		// explicitly requested repository node: treat it like a folder
+		// (links into the repository found while syncing are still ignored)
+		Folder {
+			name: String::new(),
+			url: URL::from_href(&format!(
+				"ilias.php?baseClass=ilRepositoryGUI&cmd=render&ref_id={}",
+				ref_id
+			))?,
+		}
+	} else {
+		Object::from_url(
+			URL::from_href(&sync_url).context("invalid sync URL")?,
+			String::new(),
+			None,
+		)
+		.context("invalid sync object")?
+	};
+	if let Some(zip_path) = ilias.opt.zip.as_ref().filter(|_| !ilias.opt.dry_run) {
+		archive::start(&ilias.opt.output, zip_path)?;
+	}
+	queue::spawn(process_gracefully(ilias.clone(), ilias.opt.content_dir(), obj));
 
 	while let Either::Left((task, _)) = future::select(rx.next(), future::ready(())).await {
 		if let Some(task) = task {
@@ -181,6 +297,11 @@ async fn real_main(mut opt: Opt) -> Result<()> {
 			break; // channel is empty => all tasks are completed
 		}
 	}
+	if queue::is_aborted() {
+		return Err(anyhow!("sync aborted: post-download hook failed (--strict)"));
+	} else if queue::is_stopped() {
+		warning!(format => "Interrupted, not all files were downloaded");
+	}
 	if ilias.opt.content_tree {
 		if let Err(e) = ilias
 			.download("ilias.php?baseClass=ilRepositoryGUI&cmd=frameset&set_mode=flat&ref_id=1")
@@ -189,11 +310,63 @@ async fn real_main(mut opt: Opt) -> Result<()> {
 			warning!("could not disable content tree:", e);
 		}
 	}
-	if ilias.opt.keep_session {
+	if ilias.opt.list {
+		print_list();
+	}
+	if ilias.opt.keep_session && !ilias.opt.list {
 		if let Err(e) = ilias.save_session().await.context("failed to save session cookies") {
 			warning!(e)
 		}
 	}
+	let filtered = ilias::file::FILTERED_FILES.load(Ordering::SeqCst);
+	if filtered > 0 {
+		log!(0, "Skipped {} files due to --only-ext / --skip-ext", filtered);
+	}
+	if ilias.opt.verbose_timing {
+		timing::print_summary();
+	}
+	if ilias.opt.report_duplicates {
+		info!("Looking for duplicate files..");
+		let root = ilias.opt.content_dir();
+		tokio::task::spawn_blocking(move || duplicates::report(&root))
+			.await?
+			.context("failed to find duplicate files")?;
+	}
+	if let Some(path) = ilias.opt.deadlines_ical.as_ref() {
+		let mut deadlines = ilias::exercise::DEADLINES.lock().unwrap().clone();
+		deadlines.sort_by_key(|x| x.time);
+		log!(0, "Writing {} deadlines to {}", deadlines.len(), path.display());
+		if !ilias.opt.dry_run {
+			ical::write_calendar(path, &deadlines)?;
+		}
+	}
+	if ilias.opt.generate_index && !ilias.opt.dry_run {
+		info!("Writing index pages..");
+		let root = ilias.opt.content_dir();
+		tokio::task::spawn_blocking(move || index::write_indexes(&root))
+			.await?
+			.context("failed to write index pages")?;
+	}
+	if ilias.opt.archive_format == Some(ArchiveFormat::BagIt) && !ilias.opt.dry_run {
+		info!("Writing BagIt manifest..");
+		let root = ilias.opt.output.clone();
+		tokio::task::spawn_blocking(move || bagit::write_bag(&root))
+			.await?
+			.context("failed to write BagIt tag files")?;
+	}
+	if ilias.opt.zip.is_some() && !ilias.opt.dry_run {
+		info!("Writing zip archive..");
+		tokio::task::spawn_blocking(archive::finish)
+			.await?
+			.context("failed to write zip archive")?;
+	}
+	if !ilias.opt.list {
+		stats::print_failures(&ilias.opt.content_dir());
+		stats::print_summary(start.elapsed());
+	}
+	if ilias.opt.notify {
+		tokio::task::spawn_blocking(notify_finished).await?;
+	}
 	if PROGRESS_BAR_ENABLED.load(Ordering::SeqCst) {
 		PROGRESS_BAR.set_style(ProgressStyle::default_bar().template("[{pos}/{len}] {wide_msg}")?);
 		PROGRESS_BAR.finish_with_message("done");
@@ -201,6 +374,65 @@ async fn real_main(mut opt: Opt) -> Result<()> {
 	Ok(())
 }
 
+/// Print the objects found by `--list` as a tree.
+fn print_list() {
+	let mut list = LIST.lock().unwrap();
+	list.sort();
+	for (path, line) in list.iter() {
+		let depth = path.components().count();
+		log!(0, "{}{}", "  ".repeat(depth.saturating_sub(1)), line);
+	}
+}
+
+/// Periodically check whether the session is still valid and log in again if it expired.
+async fn refresh_session(ilias: Arc<ILIAS>, period: Duration) {
+	let mut interval = tokio::time::interval(period);
+	interval.tick().await; // first tick completes immediately
+	loop {
+		interval.tick().await;
+		log!(1, "Checking session validity..");
+		let probe = ilias.get_html(DEFAULT_SYNC_URL).await.map(|_| ());
+		if let Err(e) = probe {
+			warning!("session expired:", e);
+			if let Err(e) = ilias.relogin().await {
+				error!("Logging in again"; e);
+			}
+		}
+	}
+}
+
+/// On the first Ctrl+C: stop starting new jobs, but finish the running ones (and save the session afterwards).
+/// On the second Ctrl+C: exit immediately.
+async fn handle_ctrl_c() {
+	if tokio::signal::ctrl_c().await.is_err() {
+		return;
+	}
+	warning!(format => "Interrupted, finishing running downloads.. (press Ctrl+C again to exit immediately)");
+	queue::stop();
+	if tokio::signal::ctrl_c().await.is_ok() {
+		std::process::exit(130);
+	}
+}
+
+/// Show a desktop notification with the number of processed objects and errors.
+/// Nothing happens if no notification service is available (e.g. on a server).
+fn notify_finished() {
+	let processed = stats::PROCESSED.load(Ordering::SeqCst);
+	let failed = stats::FAILED.load(Ordering::SeqCst);
+	let mut body = format!("{} objects synced", processed);
+	if failed > 0 {
+		body += &format!(", {} errors", failed);
+	}
+	if let Err(e) = notify_rust::Notification::new()
+		.summary("ILIAS sync finished")
+		.body(&body)
+		.appname(env!("CARGO_PKG_NAME"))
+		.show()
+	{
+		log!(1, "Could not show notification: {}", e);
+	}
+}
+
 // https://github.com/rust-lang/rust/issues/53690#issuecomment-418911229
 #[allow(clippy::manual_async_fn)]
 fn process_gracefully(ilias: Arc<ILIAS>, path: PathBuf, obj: Object) -> impl Future<Output = ()> + Send {
@@ -209,22 +441,40 @@ fn process_gracefully(ilias: Arc<ILIAS>, path: PathBuf, obj: Object) -> impl Fut
 	}
 	async move {
 		let permit = queue::get_ticket().await;
+		if queue::is_stopped() {
+			return;
+		}
 		let path_text = path.to_string_lossy().into_owned();
-		if let Err(e) = process(ilias, path, obj).await.context("failed to process URL") {
+		let timing = ilias
+			.opt
+			.verbose_timing
+			.then(|| (Instant::now(), format!("{} {}", obj.kind(), path_text)));
+		if let Err(e) = process(ilias, path.clone(), obj).await.context("failed to process URL") {
 			error!("Syncing {}", path_text; e);
+			stats::FAILED.fetch_add(1, Ordering::SeqCst);
+			stats::record_failure(path, e);
+		} else {
+			stats::PROCESSED.fetch_add(1, Ordering::SeqCst);
+		}
+		if let Some((start, what)) = timing {
+			timing::record(start, what);
 		}
 		drop(permit);
 	}
 }
 
-async fn handle_gracefully(fut: impl Future<Output = Result<()>>) {
+/// Like `process_gracefully`, for tasks writing a single file at `path`.
+async fn handle_gracefully(path: PathBuf, fut: impl Future<Output = Result<()>>) {
 	if let Err(e) = fut.await {
 		error!(e);
+		stats::FAILED.fetch_add(1, Ordering::SeqCst);
+		stats::record_failure(path, e);
 	}
 }
 
 async fn process(ilias: Arc<ILIAS>, path: PathBuf, obj: Object) -> Result<()> {
-	let relative_path = path.strip_prefix(&ilias.opt.output).unwrap();
+	let content_dir = ilias.opt.content_dir();
+	let relative_path = path.strip_prefix(&content_dir).unwrap();
 	if PROGRESS_BAR_ENABLED.load(Ordering::SeqCst) {
 		let path = relative_path.display().to_string();
 		if !path.is_empty() {
@@ -234,16 +484,49 @@ async fn process(ilias: Arc<ILIAS>, path: PathBuf, obj: Object) -> Result<()> {
 	// root path should not be matched
 	if relative_path.parent().is_some() && ilias.ignore.should_ignore(relative_path, obj.is_dir()) {
 		log!(1, "Ignored {}", relative_path.to_string_lossy());
+		stats::SKIPPED.fetch_add(1, Ordering::SeqCst);
 		return Ok(());
 	}
 	log!(1, "Syncing {} {}", obj.kind(), relative_path.to_string_lossy());
 	log!(2, " URL: {}", obj.url().url);
 	if obj.is_ignored_by_option(&ilias.opt) {
+		stats::SKIPPED.fetch_add(1, Ordering::SeqCst);
 		return Ok(());
 	}
-	if obj.is_dir() {
+	// only the courses and folders directly below the sync root are filtered
+	if !ilias.opt.only_ref_id.is_empty()
+		&& relative_path.components().count() == 1
+		&& matches!(obj, Course { .. } | Folder { .. })
+		&& !ilias.opt.only_ref_id.contains(&obj.url().ref_id)
+	{
+		log!(1, "Skipping {} (not in --only-ref-id)", relative_path.to_string_lossy());
+		stats::SKIPPED.fetch_add(1, Ordering::SeqCst);
+		return Ok(());
+	}
+	if let Some(max_depth) = ilias.opt.max_depth {
+		if obj.is_dir() && relative_path.components().count() > max_depth {
+			log!(1, "Max depth reached, skipping {}", relative_path.to_string_lossy());
+			stats::SKIPPED.fetch_add(1, Ordering::SeqCst);
+			return Ok(());
+		}
+	}
+	if ilias.opt.list {
+		LIST.lock().unwrap().push((
+			relative_path.to_owned(),
+			format!("{} {} {}", obj.kind(), obj.name(), obj.url().url),
+		));
+		// the other objects are only listed, their files are not
+		if !obj.has_children() {
+			return Ok(());
+		}
+	}
+	// flattened folders only exist as a prefix of the file names
+	if obj.is_dir() && !(matches!(obj, Folder { .. }) && ilias::folder::is_flat(&path, &ilias)) {
 		create_dir(&path).await?;
 	}
+	if ilias.opt.structure_only && !matches!(obj, Course { .. } | Folder { .. } | Dashboard { .. }) {
+		return Ok(());
+	}
 	match &obj {
 		Course { url, name } => {
 			ilias::course::download(path, ilias, url, name).await?;
@@ -251,8 +534,8 @@ async fn process(ilias: Arc<ILIAS>, path: PathBuf, obj: Object) -> Result<()> {
 		Folder { url, .. } | Dashboard { url } => {
 			ilias::folder::download(&path, ilias, url).await?;
 		},
-		File { url, .. } => {
-			ilias::file::download(&path, relative_path, ilias, url).await?;
+		File { url, version, .. } => {
+			ilias::file::download(&path, relative_path, ilias, url, version.as_deref()).await?;
 		},
 		PluginDispatch { url, .. } => {
 			ilias::plugin_dispatch::download(&path, ilias, url).await?;
@@ -263,6 +546,12 @@ async fn process(ilias: Arc<ILIAS>, path: PathBuf, obj: Object) -> Result<()> {
 		Forum { url, .. } => {
 			ilias::forum::download(&path, ilias, url).await?;
 		},
+		Blog { url, .. } => {
+			ilias::blog::download(&path, relative_path, ilias, url).await?;
+		},
+		DataCollection { url, .. } => {
+			ilias::data_collection::download(&path, relative_path, ilias, url).await?;
+		},
 		Thread { url } => {
 			ilias::thread::download(&path, relative_path, ilias, url).await?;
 		},
@@ -272,20 +561,43 @@ async fn process(ilias: Arc<ILIAS>, path: PathBuf, obj: Object) -> Result<()> {
 		Weblink { url, .. } => {
 			ilias::weblink::download(&path, relative_path, ilias, url).await?;
 		},
-		Wiki { .. } => {
-			log!(1, "Ignored wiki!");
+		Wiki { url, .. } => {
+			ilias::wiki::download(&path, relative_path, ilias, url).await?;
+		},
+		Glossary { url, .. } => {
+			ilias::glossary::download(&path, relative_path, ilias, url).await?;
+		},
+		QuestionPool { url, .. } => {
+			ilias::question_pool::download(&path, relative_path, ilias, url).await?;
 		},
-		Survey { .. } => {
-			log!(1, "Ignored survey!");
+		Survey { url, .. } => {
+			ilias::survey::download(&path, relative_path, ilias, url).await?;
 		},
-		Presentation { .. } => {
-			log!(
-				1,
-				"Ignored interactive presentation! (visit it yourself, it's probably interesting)"
-			);
+		Test { url, .. } => {
+			ilias::test::download(&path, relative_path, ilias, url).await?;
 		},
-		Generic { .. } => {
-			log!(1, "Ignored generic {:?}", obj)
+		Presentation { url, .. } => {
+			ilias::presentation::download(&path, relative_path, ilias, url).await?;
+		},
+		MediaCast { url, .. } => {
+			ilias::mediacast::download(&path, ilias, url).await?;
+		},
+		Generic { url, name } => {
+			let mut resolved = None;
+			if ilias.opt.resolve_shortlinks && url.is_goto() {
+				// the real target is only revealed by following the redirect
+				let head = ilias.head(url.url.as_str()).await.context("HEAD request failed")?;
+				let target = URL::from_href(head.url().as_str())?;
+				if target.url != url.url {
+					log!(2, "Resolved shortlink {} to {}", url.url, target.url);
+					resolved = Some(Object::from_url(target, name.clone(), None));
+				}
+			}
+			match resolved {
+				Some(Ok(Generic { .. })) | None => log!(1, "Ignored generic {:?}", obj),
+				Some(Ok(resolved)) => queue::spawn(process_gracefully(ilias.clone(), path.clone(), resolved)),
+				Some(Err(e)) => warning!(1; "could not resolve shortlink {}: {:?}", url.url, e),
+			}
 		},
 	}
 	if PROGRESS_BAR_ENABLED.load(Ordering::SeqCst) {
@@ -293,3 +605,34 @@ async fn process(ilias: Arc<ILIAS>, path: PathBuf, obj: Object) -> Result<()> {
 	}
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn corrupted_session_is_removed() {
+		let dir = tempfile::tempdir().unwrap();
+		let session_path = dir.path().join(".iliassession");
+		std::fs::write(&session_path, "{\"cookies\": [garbage").unwrap();
+		assert!(read_cookies(&session_path).is_err());
+
+		let opt = Opt::from_iter(&[OsString::from("ilias"), "-o".into(), dir.path().into()]);
+		let ignore = IliasIgnore::load(opt.output.clone(), opt.content_dir()).unwrap();
+		let err = match try_to_load_session(opt, ignore, CourseNames::default()).await {
+			Ok(_) => panic!("corrupted session was loaded"),
+			Err(e) => e,
+		};
+		assert!(format!("{:?}", err).contains("session file is corrupted"), "{:?}", err);
+		assert!(!session_path.exists());
+	}
+
+	#[test]
+	fn missing_session_is_io_error() {
+		// only parse errors remove the session file
+		let dir = tempfile::tempdir().unwrap();
+		let session_path = dir.path().join(".iliassession");
+		let err = read_cookies(&session_path).unwrap_err();
+		assert!(err.downcast_ref::<std::io::Error>().is_some());
+	}
+}
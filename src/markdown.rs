@@ -0,0 +1,137 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use scraper::{ElementRef, Node};
+
+use crate::util::absolute_url;
+
+static WHITESPACE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\s+").unwrap());
+static BLANK_LINES: Lazy<Regex> = Lazy::new(|| Regex::new(r"\n[ \t]*\n(\s*\n)+").unwrap());
+
+/// Convert the contents of the element to Markdown.
+/// Only the common formatting (paragraphs, headings, emphasis, links, images, lists, quotes and code) is kept.
+pub fn to_markdown(element: ElementRef) -> String {
+	let mut out = String::new();
+	convert_children(element, &mut out, &mut Vec::new());
+	BLANK_LINES.replace_all(out.trim(), "\n\n").into_owned() + "\n"
+}
+
+/// `lists` contains the open lists: the next item number for ordered lists, `None` for unordered lists
+fn convert_children(element: ElementRef, out: &mut String, lists: &mut Vec<Option<usize>>) {
+	for child in element.children() {
+		if let Some(child) = ElementRef::wrap(child) {
+			convert(child, out, lists);
+		} else if let Node::Text(text) = child.value() {
+			let text = WHITESPACE.replace_all(text, " ");
+			// avoid multiple spaces at the start of a line / between elements
+			if out.is_empty() || out.ends_with(char::is_whitespace) {
+				out.push_str(text.trim_start());
+			} else {
+				out.push_str(&text);
+			}
+		}
+	}
+}
+
+fn convert(node: ElementRef, out: &mut String, lists: &mut Vec<Option<usize>>) {
+	let element = node.value();
+	let children = |out: &mut String, lists: &mut Vec<Option<usize>>| convert_children(node, out, lists);
+	match element.name() {
+		"script" | "style" => {},
+		"br" => out.push_str("  \n"),
+		"hr" => out.push_str("\n\n---\n\n"),
+		"p" | "div" | "table" | "tr" => {
+			out.push_str("\n\n");
+			children(out, lists);
+			out.push_str("\n\n");
+		},
+		"td" | "th" => {
+			children(out, lists);
+			out.push(' ');
+		},
+		"h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+			let level = element.name()[1..].parse().unwrap_or(1);
+			out.push_str("\n\n");
+			out.push_str(&"#".repeat(level));
+			out.push(' ');
+			children(out, lists);
+			out.push_str("\n\n");
+		},
+		"strong" | "b" => {
+			out.push_str("**");
+			children(out, lists);
+			out.push_str("**");
+		},
+		"em" | "i" => {
+			out.push('*');
+			children(out, lists);
+			out.push('*');
+		},
+		"code" => {
+			out.push('`');
+			children(out, lists);
+			out.push('`');
+		},
+		"pre" => {
+			let text = node.text().collect::<String>();
+			out.push_str("\n\n```\n");
+			out.push_str(text.trim_end());
+			out.push_str("\n```\n\n");
+		},
+		"a" => match element.attr("href") {
+			Some(href) => {
+				out.push('[');
+				children(out, lists);
+				out.push_str(&format!("]({})", absolute_url(href)));
+			},
+			None => children(out, lists),
+		},
+		"img" => {
+			if let Some(src) = element.attr("src") {
+				out.push_str(&format!(
+					"![{}]({})",
+					element.attr("alt").unwrap_or_default(),
+					absolute_url(src)
+				));
+			}
+		},
+		"ul" | "ol" => {
+			let nested = !lists.is_empty();
+			lists.push(if element.name() == "ol" { Some(1) } else { None });
+			if !nested {
+				out.push('\n');
+			}
+			children(out, lists);
+			lists.pop();
+			if !nested {
+				out.push_str("\n\n");
+			}
+		},
+		"li" => {
+			out.push('\n');
+			out.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+			match lists.last_mut() {
+				Some(Some(number)) => {
+					out.push_str(&format!("{}. ", number));
+					*number += 1;
+				},
+				_ => out.push_str("- "),
+			}
+			children(out, lists);
+		},
+		"blockquote" => {
+			let mut quote = String::new();
+			children(&mut quote, lists);
+			let quote = BLANK_LINES.replace_all(quote.trim(), "\n\n");
+			out.push_str("\n\n");
+			for line in quote.lines() {
+				out.push_str("> ");
+				out.push_str(line);
+				out.push('\n');
+			}
+			out.push('\n');
+		},
+		_ => children(out, lists),
+	}
+}
@@ -1,8 +1,13 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
 use futures::Future;
 use futures_channel::mpsc::{UnboundedReceiver, UnboundedSender};
 use once_cell::sync::{Lazy, OnceCell};
+use rand::Rng;
 use tokio::{
-	sync::{Semaphore, SemaphorePermit},
+	sync::{OwnedSemaphorePermit, Semaphore, SemaphorePermit},
 	task::{self, JoinHandle},
 	time,
 };
@@ -11,25 +16,134 @@ use tokio::{
 static TASKS: OnceCell<UnboundedSender<JoinHandle<()>>> = OnceCell::new();
 static TASKS_RUNNING: Lazy<Semaphore> = Lazy::new(|| Semaphore::new(0));
 static REQUEST_TICKETS: Lazy<Semaphore> = Lazy::new(|| Semaphore::new(0));
+/// One ticket per byte, shared by all downloads (`--max-bandwidth`)
+static BANDWIDTH_TICKETS: Lazy<Semaphore> = Lazy::new(|| Semaphore::new(0));
+static MAX_BANDWIDTH: OnceCell<usize> = OnceCell::new();
+/// Concurrent requests per host (`--per-host-jobs`)
+static PER_HOST_JOBS: OnceCell<usize> = OnceCell::new();
+static HOST_TICKETS: Lazy<Mutex<HashMap<String, Arc<Semaphore>>>> = Lazy::new(Mutex::default);
+/// Set once the user interrupted the program (Ctrl+C)
+static STOPPED: AtomicBool = AtomicBool::new(false);
+/// Set once a job failed in a way that should abort the whole sync (`--strict`)
+static ABORTED: AtomicBool = AtomicBool::new(false);
 
 pub async fn get_request_ticket() {
 	REQUEST_TICKETS.acquire().await.unwrap().forget();
 }
 
+/// Allow any number of requests (used by the tests)
+#[cfg(test)]
+pub fn disable_rate_limit() {
+	REQUEST_TICKETS.add_permits(Semaphore::MAX_PERMITS - REQUEST_TICKETS.available_permits());
+}
+
+/// Wait until the bandwidth limit (if any) allows to receive this many bytes.
+pub async fn get_bandwidth_tickets(bytes: usize) {
+	let max = match MAX_BANDWIDTH.get() {
+		Some(&max) => max,
+		None => return,
+	};
+	let mut remaining = bytes;
+	while remaining > 0 {
+		let n = remaining.min(max).min(u32::MAX as usize);
+		BANDWIDTH_TICKETS.acquire_many(n as u32).await.unwrap().forget();
+		remaining -= n;
+	}
+}
+
+/// Ticket of a host (see `get_host_ticket`), stored in the extensions of a response
+/// and held until its body was received (see `util::body_stream`).
+#[derive(Clone)]
+pub struct HostTicket {
+	_permit: Arc<OwnedSemaphorePermit>,
+}
+
+impl From<OwnedSemaphorePermit> for HostTicket {
+	fn from(permit: OwnedSemaphorePermit) -> Self {
+		HostTicket {
+			_permit: Arc::new(permit),
+		}
+	}
+}
+
+/// Wait until another request to this host may be sent (if `--per-host-jobs` is set).
+/// The request may be sent and its response received as long as the returned permit is held.
+pub async fn get_host_ticket(host: &str) -> Option<OwnedSemaphorePermit> {
+	let jobs = *PER_HOST_JOBS.get()?;
+	let semaphore = Arc::clone(
+		HOST_TICKETS
+			.lock()
+			.unwrap()
+			.entry(host.to_owned())
+			.or_insert_with(|| Arc::new(Semaphore::new(jobs))),
+	);
+	semaphore.acquire_owned().await.ok()
+}
+
+pub fn set_per_host_jobs(jobs: usize) {
+	PER_HOST_JOBS.get_or_init(|| jobs.max(1));
+}
+
 pub async fn get_ticket() -> SemaphorePermit<'static> {
 	TASKS_RUNNING.acquire().await.unwrap()
 }
 
+/// Don't start any new jobs. Running jobs are finished normally.
+pub fn stop() {
+	STOPPED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_stopped() -> bool {
+	STOPPED.load(Ordering::SeqCst)
+}
+
+/// Like `stop`, but the sync is reported as failed afterwards.
+pub fn abort() {
+	ABORTED.store(true, Ordering::SeqCst);
+	stop();
+}
+
+pub fn is_aborted() -> bool {
+	ABORTED.load(Ordering::SeqCst)
+}
+
 pub fn spawn(e: impl Future<Output = ()> + Send + 'static) {
 	TASKS.get().unwrap().unbounded_send(task::spawn(e)).unwrap();
 }
 
-pub fn set_download_rate(rate: usize) {
+/// Release `rate` request tickets per minute (token bucket holding at most `burst` tickets).
+/// Each ticket is delayed by an additional random amount of up to `jitter` milliseconds.
+pub fn set_download_rate(rate: usize, burst: usize, jitter: u64) {
+	let burst = burst.max(1);
+	REQUEST_TICKETS.add_permits(burst);
+	task::spawn(async move {
+		let period = time::Duration::from_secs_f64(60.0 / rate as f64);
+		loop {
+			let delay = if jitter > 0 {
+				rand::thread_rng().gen_range(0..=jitter)
+			} else {
+				0
+			};
+			time::sleep(period + time::Duration::from_millis(delay)).await;
+			// unused tickets accumulate up to the burst size
+			if REQUEST_TICKETS.available_permits() < burst {
+				REQUEST_TICKETS.add_permits(1);
+			}
+		}
+	});
+}
+
+/// Release `bytes_per_second` bandwidth tickets per second (token bucket holding at most one second worth of tickets).
+pub fn set_max_bandwidth(bytes_per_second: usize) {
+	let bytes_per_second = bytes_per_second.max(1);
+	MAX_BANDWIDTH.get_or_init(|| bytes_per_second);
 	task::spawn(async move {
-		let mut interval = time::interval(time::Duration::from_secs_f64(60.0 / rate as f64));
+		let period = time::Duration::from_millis(100);
+		let per_period = (bytes_per_second / 10).max(1);
 		loop {
-			interval.tick().await;
-			REQUEST_TICKETS.add_permits(1);
+			let available = BANDWIDTH_TICKETS.available_permits();
+			BANDWIDTH_TICKETS.add_permits(per_period.min(bytes_per_second.saturating_sub(available)));
+			time::sleep(period).await;
 		}
 	});
 }
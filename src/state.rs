@@ -0,0 +1,123 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{atomic::Ordering, Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+
+use crate::cli::DRY_RUN;
+
+/// Name of the state file in the output directory
+pub const STATE_FILE: &str = ".iliasstate";
+
+/// What is known about a previously downloaded URL
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Entry {
+	pub etag: Option<String>,
+	pub last_modified: Option<String>,
+	pub size: Option<u64>,
+	/// File version listed by ILIAS
+	pub version: Option<String>,
+	/// File name sent by the server
+	pub name: Option<String>,
+}
+
+impl Entry {
+	fn from_json(value: &Value) -> Option<(String, Self)> {
+		let string = |key: &str| value.get(key).and_then(|x| x.as_str()).map(ToOwned::to_owned);
+		Some((
+			string("url")?,
+			Entry {
+				etag: string("etag"),
+				last_modified: string("last_modified"),
+				size: value.get("size").and_then(|x| x.as_u64()),
+				version: string("version"),
+				name: string("name"),
+			},
+		))
+	}
+
+	fn to_json(&self, url: &str) -> Value {
+		json!({
+			"url": url,
+			"etag": self.etag,
+			"last_modified": self.last_modified,
+			"size": self.size,
+			"version": self.version,
+			"name": self.name,
+		})
+	}
+}
+
+/// Persisted download state, stored as one JSON object per line.
+/// Updates are appended to the file immediately, so an interrupted run loses nothing.
+/// Later lines override earlier lines for the same URL.
+pub struct State {
+	path: PathBuf,
+	entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl State {
+	/// Load the state file in the output directory.
+	/// Unreadable lines (e.g. the last line of an interrupted run) are ignored.
+	pub fn load(output: &Path) -> Result<Self> {
+		let path = output.join(STATE_FILE);
+		let mut entries = HashMap::new();
+		if let Ok(content) = fs::read_to_string(&path) {
+			for line in content.lines() {
+				if let Some((url, entry)) = serde_json::from_str(line).ok().as_ref().and_then(Entry::from_json) {
+					entries.insert(url, entry);
+				}
+			}
+		}
+		let state = State {
+			path,
+			entries: Mutex::new(entries),
+		};
+		state.compact().context("failed to write state file")?;
+		Ok(state)
+	}
+
+	/// Rewrite the state file with one line per URL.
+	fn compact(&self) -> Result<()> {
+		if DRY_RUN.load(Ordering::SeqCst) {
+			return Ok(());
+		}
+		let entries = self.entries.lock().map_err(|x| anyhow!("{}", x))?;
+		let tmp = self.path.with_extension("tmp");
+		let mut writer = BufWriter::new(File::create(&tmp)?);
+		for (url, entry) in entries.iter() {
+			writeln!(writer, "{}", entry.to_json(url))?;
+		}
+		writer.flush()?;
+		drop(writer);
+		fs::rename(&tmp, &self.path)?;
+		Ok(())
+	}
+
+	pub fn get(&self, url: &str) -> Option<Entry> {
+		self.entries.lock().ok()?.get(url).cloned()
+	}
+
+	/// Update the entry of the URL and append it to the state file.
+	pub fn update(&self, url: &str, f: impl FnOnce(&mut Entry)) -> Result<()> {
+		let mut entries = self.entries.lock().map_err(|x| anyhow!("{}", x))?;
+		let entry = entries.entry(url.to_owned()).or_default();
+		let old = entry.clone();
+		f(entry);
+		if *entry == old || DRY_RUN.load(Ordering::SeqCst) {
+			return Ok(());
+		}
+		let mut file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(&self.path)
+			.context("failed to open state file")?;
+		writeln!(file, "{}", entry.to_json(url)).context("failed to write state file")?;
+		Ok(())
+	}
+}
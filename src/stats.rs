@@ -0,0 +1,71 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+
+use crate::duplicates::format_size;
+
+/// Number of objects that were processed successfully
+pub static PROCESSED: AtomicUsize = AtomicUsize::new(0);
+/// Number of objects that could not be processed
+pub static FAILED: AtomicUsize = AtomicUsize::new(0);
+/// Number of files that were downloaded (new or updated)
+pub static DOWNLOADED: AtomicUsize = AtomicUsize::new(0);
+/// Number of bytes received while downloading files
+pub static BYTES: AtomicU64 = AtomicU64::new(0);
+/// Number of objects and files that were skipped (ignored, filtered or not changed)
+pub static SKIPPED: AtomicUsize = AtomicUsize::new(0);
+pub static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+pub static ERRORS: AtomicUsize = AtomicUsize::new(0);
+/// Errors of the failed objects / files (listed again at the end of the sync)
+static FAILURES: Lazy<Mutex<Vec<(PathBuf, anyhow::Error)>>> = Lazy::new(Mutex::default);
+
+/// Remember the error, so it can be listed by `print_failures`.
+pub fn record_failure(path: PathBuf, error: anyhow::Error) {
+	FAILURES.lock().unwrap().push((path, error));
+}
+
+/// List all recorded errors, grouped by the path of the object (relative to `root`).
+pub fn print_failures(root: &Path) {
+	let failures = FAILURES.lock().unwrap();
+	if failures.is_empty() {
+		return;
+	}
+	let mut grouped = BTreeMap::<_, Vec<_>>::new();
+	for (path, error) in failures.iter() {
+		grouped
+			.entry(path.strip_prefix(root).unwrap_or(path))
+			.or_default()
+			.push(error);
+	}
+	info!("Failed objects:");
+	for (path, errors) in grouped {
+		log!(0, "  {}", path.display());
+		for error in errors {
+			log!(0, "    {}", format!("{:?}", error).replace('\n', "\n    ").bright_red());
+		}
+	}
+}
+
+/// Print the counters collected during the sync.
+pub fn print_summary(elapsed: Duration) {
+	let load = |counter: &AtomicUsize| counter.load(Ordering::SeqCst);
+	info!("Summary:");
+	log!(0, "  {} objects processed, {} failed", load(&PROCESSED), load(&FAILED));
+	log!(
+		0,
+		"  {} files downloaded ({}), {} skipped",
+		load(&DOWNLOADED),
+		format_size(BYTES.load(Ordering::SeqCst)),
+		load(&SKIPPED)
+	);
+	log!(0, "  {} warnings, {} errors", load(&WARNINGS), load(&ERRORS));
+	let secs = elapsed.as_secs();
+	let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+	log!(0, "  finished in {}:{:02}:{:02}", hours, minutes, seconds);
+}
@@ -0,0 +1,175 @@
+//! Local HTTP server used as the ILIAS instance in tests.
+//! Tests register the responses for the URLs they request using `serve`.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use structopt::StructOpt;
+use tempfile::TempDir;
+
+use crate::cli::{Opt, BASE_URL};
+use crate::course_names::CourseNames;
+use crate::ilias::ILIAS;
+use crate::iliasignore::IliasIgnore;
+use crate::queue;
+
+#[derive(Clone)]
+pub struct Response {
+	pub status: u16,
+	pub headers: Vec<(String, String)>,
+	pub body: Vec<u8>,
+}
+
+impl Response {
+	pub fn html(body: &str) -> Self {
+		Response {
+			status: 200,
+			headers: vec![("Content-Type".to_owned(), "text/html; charset=UTF-8".to_owned())],
+			body: body.as_bytes().to_vec(),
+		}
+	}
+
+	pub fn file(body: &[u8]) -> Self {
+		Response {
+			status: 200,
+			headers: vec![("Content-Type".to_owned(), "application/octet-stream".to_owned())],
+			body: body.to_vec(),
+		}
+	}
+
+	pub fn status(mut self, status: u16) -> Self {
+		self.status = status;
+		self
+	}
+
+	pub fn header(mut self, name: &str, value: &str) -> Self {
+		self.headers.push((name.to_owned(), value.to_owned()));
+		self
+	}
+}
+
+/// Pattern for the request target and the responses to send (in order, the last one is repeated)
+type Route = (String, Vec<Response>);
+
+/// Registered routes, the first matching one is used
+static ROUTES: Lazy<Mutex<Vec<Route>>> = Lazy::new(Mutex::default);
+/// Request line and headers of all received requests
+static REQUESTS: Lazy<Mutex<Vec<String>>> = Lazy::new(Mutex::default);
+
+static SERVER: Lazy<String> = Lazy::new(|| {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let url = format!("http://{}/", listener.local_addr().unwrap());
+	std::thread::spawn(move || {
+		for stream in listener.incoming().flatten() {
+			std::thread::spawn(move || handle(stream));
+		}
+	});
+	BASE_URL.set(url.clone()).expect("base URL already set");
+	queue::disable_rate_limit();
+	url
+});
+
+/// Starts the server (if not running yet) and returns its URL, which is also used as the ILIAS URL.
+pub fn base_url() -> &'static str {
+	&SERVER
+}
+
+/// Send `response` for all requests whose target (path and query) contains `pattern`.
+pub fn serve(pattern: &str, response: Response) {
+	serve_sequence(pattern, vec![response]);
+}
+
+/// Like `serve`, but the responses are sent one after another (the last one is repeated).
+pub fn serve_sequence(pattern: &str, responses: Vec<Response>) {
+	base_url();
+	ROUTES.lock().unwrap().push((pattern.to_owned(), responses));
+}
+
+/// Returns the request lines (method and target) of the requests whose target contains `pattern`.
+pub fn requests(pattern: &str) -> Vec<String> {
+	request_heads(pattern)
+		.into_iter()
+		.map(|x| x.lines().next().unwrap().rsplit_once(' ').unwrap().0.to_owned())
+		.collect()
+}
+
+/// Returns the request lines and headers of the requests whose target contains `pattern`.
+pub fn request_heads(pattern: &str) -> Vec<String> {
+	REQUESTS
+		.lock()
+		.unwrap()
+		.iter()
+		.filter(|x| x.lines().next().unwrap().contains(pattern))
+		.cloned()
+		.collect()
+}
+
+/// Creates an ILIAS instance using the test server, with an empty output directory.
+pub async fn ilias(args: &[&str]) -> (ILIAS, TempDir) {
+	base_url();
+	let dir = tempfile::tempdir().unwrap();
+	let mut all_args = vec!["ilias".to_owned(), "-o".to_owned(), dir.path().display().to_string()];
+	all_args.extend(args.iter().map(|x| x.to_string()));
+	let opt = Opt::from_iter(all_args);
+	let ignore = IliasIgnore::load(opt.output.clone(), opt.content_dir()).unwrap();
+	let cookies = std::sync::Arc::new(reqwest_cookie_store::CookieStoreMutex::default());
+	let ilias = ILIAS::with_session(opt, cookies, ignore, CourseNames::default())
+		.await
+		.unwrap();
+	(ilias, dir)
+}
+
+fn handle(mut stream: TcpStream) {
+	let mut reader = BufReader::new(stream.try_clone().unwrap());
+	let mut request_line = String::new();
+	if reader.read_line(&mut request_line).is_err() {
+		return;
+	}
+	let mut request_head = request_line.clone();
+	let mut content_length = 0;
+	loop {
+		let mut line = String::new();
+		if reader.read_line(&mut line).unwrap_or(0) == 0 || line.trim().is_empty() {
+			break;
+		}
+		request_head += &line;
+		if let Some((name, value)) = line.split_once(':') {
+			if name.eq_ignore_ascii_case("content-length") {
+				content_length = value.trim().parse().unwrap_or(0);
+			}
+		}
+	}
+	let mut body = vec![0; content_length];
+	let _ = reader.read_exact(&mut body);
+
+	let mut parts = request_line.split_whitespace();
+	let method = parts.next().unwrap_or_default();
+	let target = parts.next().unwrap_or_default();
+	REQUESTS.lock().unwrap().push(request_head);
+	let mut routes = ROUTES.lock().unwrap();
+	let response = match routes.iter_mut().find(|x| target.contains(&x.0)) {
+		Some((_, responses)) if responses.len() > 1 => responses.remove(0),
+		Some((_, responses)) => responses[0].clone(),
+		None => Response {
+			status: 404,
+			headers: Vec::new(),
+			body: b"not found".to_vec(),
+		},
+	};
+	drop(routes);
+	let (status, headers, body) = (response.status, &response.headers, &response.body);
+	let mut head = format!("HTTP/1.1 {} Test\r\nConnection: close\r\n", status);
+	if !headers.iter().any(|x| x.0.eq_ignore_ascii_case("content-length")) {
+		head += &format!("Content-Length: {}\r\n", body.len());
+	}
+	for (name, value) in headers {
+		head += &format!("{}: {}\r\n", name, value);
+	}
+	head += "\r\n";
+	let _ = stream.write_all(head.as_bytes());
+	if method != "HEAD" {
+		let _ = stream.write_all(body);
+	}
+}
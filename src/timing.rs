@@ -0,0 +1,37 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+
+/// Processing durations of all objects (only recorded with `--verbose-timing`)
+static TIMINGS: Lazy<Mutex<Vec<(Duration, String)>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Number of objects listed in the summary
+const SUMMARY_LEN: usize = 10;
+
+/// Log and record the time elapsed since `start`.
+pub fn record(start: Instant, what: String) {
+	let elapsed = start.elapsed();
+	log!(0, "{:>8.2?} {}", elapsed, what);
+	TIMINGS.lock().unwrap().push((elapsed, what));
+}
+
+/// Log the time a request took.
+pub fn record_request(start: Instant, method: &str, url: &str) {
+	log!(0, "{:>8.2?} {} {}", start.elapsed(), method, url);
+}
+
+/// Print the slowest objects.
+pub fn print_summary() {
+	let mut timings = TIMINGS.lock().unwrap();
+	if timings.is_empty() {
+		return;
+	}
+	timings.sort_by_key(|(elapsed, _)| std::cmp::Reverse(*elapsed));
+	info!("Slowest objects:");
+	for (elapsed, what) in timings.iter().take(SUMMARY_LEN) {
+		log!(0, "{:>8.2?} {}", elapsed, what);
+	}
+}
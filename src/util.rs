@@ -1,61 +1,441 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use anyhow::Context;
+use async_compression::tokio::{bufread::GzipDecoder, write::GzipEncoder};
 use bytes::Bytes;
-use futures::TryStreamExt;
+use futures::{StreamExt, TryStreamExt};
+use indicatif::{ProgressBar, ProgressFinish, ProgressStyle};
+use sha2::{Digest, Sha256};
+use time::{format_description::well_known::Rfc2822, OffsetDateTime};
 use tokio::fs::File as AsyncFile;
-use tokio::io::{AsyncRead, BufWriter};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufWriter};
 use tokio_util::io::StreamReader;
+use unicode_normalization::UnicodeNormalization;
 
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
 
-use crate::{Result, ILIAS_URL};
+use crate::{
+	archive,
+	cli::{ilias_url, Opt, OverwritePolicy, DRY_RUN, FILE_MODE, MAX_NAME_LENGTH, MULTI_PROGRESS, PROGRESS_BAR_ENABLED},
+	duplicates::format_size,
+	queue, stats, Result,
+};
 
 /// Prepends a doctype and a base URL to the HTML fragment.
 pub fn wrap_html(html_fragment: &str) -> String {
-	format!("<!DOCTYPE html>\n<base href=\"{}\">{}", ILIAS_URL, html_fragment)
+	format!("<!DOCTYPE html>\n<base href=\"{}\">{}", ilias_url(), html_fragment)
+}
+
+/// Make links relative to ILIAS absolute.
+pub fn absolute_url(url: &str) -> String {
+	if url.contains("://") || url.starts_with("mailto:") || url.starts_with('#') || url.starts_with("data:") {
+		url.to_owned()
+	} else {
+		format!("{}{}", ilias_url(), url.trim_start_matches("./"))
+	}
+}
+
+/// Escapes the characters that have a special meaning in HTML.
+pub fn escape_html(s: &str) -> String {
+	s.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+}
+
+/// Limit the stream to the `--max-bandwidth` (shared by all downloads)
+/// and show the progress of the download (if the progress bar is enabled).
+fn throttle(
+	path: &Path,
+	stream: impl futures::Stream<Item = Result<Bytes, reqwest::Error>> + Unpin,
+) -> impl futures::Stream<Item = io::Result<Bytes>> + Unpin {
+	let bar = PROGRESS_BAR_ENABLED.load(Ordering::SeqCst).then(|| {
+		let bar = ProgressBar::new_spinner()
+			.with_style(ProgressStyle::with_template("  {bytes:>10} {bytes_per_sec:>12} {wide_msg}").unwrap())
+			.with_message(path.file_name().unwrap_or_default().to_string_lossy().into_owned())
+			.with_finish(ProgressFinish::AndClear);
+		MULTI_PROGRESS.add(bar)
+	});
+	Box::pin(
+		stream
+			.map_err(|x| io::Error::new(io::ErrorKind::Other, x))
+			.and_then(move |chunk| {
+				let bar = bar.clone();
+				async move {
+					queue::get_bandwidth_tickets(chunk.len()).await;
+					stats::BYTES.fetch_add(chunk.len() as u64, Ordering::SeqCst);
+					if let Some(bar) = bar {
+						bar.inc(chunk.len() as u64);
+					}
+					Ok(chunk)
+				}
+			}),
+	)
 }
 
 pub async fn write_stream_to_file(
 	path: &Path,
 	stream: impl futures::Stream<Item = Result<Bytes, reqwest::Error>> + Unpin,
 ) -> Result<()> {
-	let mut reader = StreamReader::new(stream.map_err(|x| io::Error::new(io::ErrorKind::Other, x)));
+	let mut reader = StreamReader::new(throttle(path, stream));
 	write_file_data(&path, &mut reader).await?;
 	Ok(())
 }
 
+/// The body of the response. The ticket of its host (if any) is held until the body was received.
+pub fn body_stream(resp: reqwest::Response) -> impl futures::Stream<Item = Result<Bytes, reqwest::Error>> + Unpin {
+	let ticket = resp.extensions().get::<queue::HostTicket>().cloned();
+	resp.bytes_stream().inspect(move |_| {
+		let _ = &ticket;
+	})
+}
+
+/// Write the data of the stream to the file, or append it if `resume` is set (used to resume interrupted downloads).
+/// Unlike `write_stream_to_file`, the file is not added to the zip archive (`--zip`):
+/// the caller has to check that the download is complete first.
+pub async fn write_download(
+	path: &Path,
+	stream: impl futures::Stream<Item = Result<Bytes, reqwest::Error>> + Unpin,
+	resume: bool,
+) -> Result<()> {
+	if DRY_RUN.load(Ordering::SeqCst) {
+		return Ok(());
+	}
+	let mut reader = StreamReader::new(throttle(path, stream));
+	if !resume {
+		return create_file(path, &mut reader).await;
+	}
+	let file = tokio::fs::OpenOptions::new()
+		.append(true)
+		.open(path)
+		.await
+		.context("failed to open file")?;
+	copy_to_file(&mut reader, file).await
+}
+
+/// Copy all data to the file.
+/// If reading fails, the data received so far is still written (interrupted downloads are resumed later).
+async fn copy_to_file<R: ?Sized>(data: &mut R, file: AsyncFile) -> Result<()>
+where
+	R: AsyncRead + Unpin,
+{
+	let mut file = BufWriter::new(file);
+	let result = tokio::io::copy(data, &mut file).await;
+	file.flush().await.context("failed to write to file")?;
+	result.context("failed to write to file")?;
+	Ok(())
+}
+
+/// Check that `size` bytes can be written next to `path` and that at least `min_free` bytes remain free afterwards.
+pub fn ensure_free_space(path: &Path, size: u64, min_free: u64) -> Result<()> {
+	let dir = match path.parent() {
+		Some(dir) if !dir.as_os_str().is_empty() => dir,
+		_ => Path::new("."),
+	};
+	let available = match fs2::available_space(dir) {
+		Ok(available) => available,
+		// not supported on this filesystem, just try to download the file
+		Err(_) => return Ok(()),
+	};
+	if available < size.saturating_add(min_free) {
+		return Err(anyhow::anyhow!(
+			"not enough free disk space for {}: {} needed, {} available",
+			path.display(),
+			format_size(size.saturating_add(min_free)),
+			format_size(available)
+		));
+	}
+	Ok(())
+}
+
+/// Path of the marker file that exists while the file is being downloaded.
+pub fn partial_download_marker(path: &Path) -> PathBuf {
+	let mut name = std::ffi::OsString::from(".");
+	name.push(path.file_name().unwrap_or_default());
+	name.push(".part");
+	path.with_file_name(name)
+}
+
+/// Returns true if a previous download of the file was interrupted.
+pub async fn is_partial_download(path: &Path) -> bool {
+	tokio::fs::metadata(partial_download_marker(path)).await.is_ok()
+}
+
 /// Write all data to the specified path. Will overwrite previous file data.
+/// The file is also added to the zip archive, if one is written (`--zip`).
 pub async fn write_file_data<R: ?Sized>(path: impl AsRef<Path>, data: &mut R) -> Result<()>
 where
 	R: AsyncRead + Unpin,
 {
-	let file = AsyncFile::create(path.as_ref())
-		.await
-		.context("failed to create file")?;
-	let mut file = BufWriter::new(file);
-	tokio::io::copy(data, &mut file)
+	if DRY_RUN.load(Ordering::SeqCst) {
+		return Ok(());
+	}
+	create_file(path.as_ref(), data).await?;
+	archive::add_file(path.as_ref()).await
+}
+
+async fn create_file<R>(path: &Path, data: &mut R) -> Result<()>
+where
+	R: AsyncRead + Unpin + ?Sized,
+{
+	let file = AsyncFile::create(path).await.context("failed to create file")?;
+	copy_to_file(data, file).await?;
+	set_permissions(path, false).await
+}
+
+/// Write an HTML page to the specified path.
+/// If `compress` is set (`--compress-html`), the page is saved gzip-compressed with an additional `.gz` extension.
+pub async fn write_html(path: impl AsRef<Path>, html: &str, compress: bool) -> Result<()> {
+	if !compress || DRY_RUN.load(Ordering::SeqCst) {
+		return write_file_data(path, &mut html.as_bytes()).await;
+	}
+	let mut path = path.as_ref().as_os_str().to_owned();
+	path.push(".gz");
+	let file = AsyncFile::create(&path).await.context("failed to create file")?;
+	let mut encoder = GzipEncoder::new(BufWriter::new(file));
+	encoder
+		.write_all(html.as_bytes())
 		.await
 		.context("failed to write to file")?;
+	encoder.shutdown().await.context("failed to write to file")?;
+	set_permissions(path.as_ref(), false).await?;
+	archive::add_file(path.as_ref()).await
+}
+
+/// Save an overview page of ILIAS (`--save-ilias-pages`), respecting the `--overwrite-policy`.
+pub async fn write_ilias_page(path: &Path, html: &str, opt: &Opt) -> Result<()> {
+	let mut existing = path.as_os_str().to_owned();
+	if opt.compress_html {
+		existing.push(".gz");
+	}
+	let existing = Path::new(&existing);
+	match opt.overwrite_policy {
+		OverwritePolicy::Always => {},
+		OverwritePolicy::IfChanged => {
+			if read_html(existing, opt.compress_html).await.ok().as_deref() == Some(html.as_bytes()) {
+				log!(2, "Skipping {}, page did not change", path.display());
+				return Ok(());
+			}
+		},
+		OverwritePolicy::Never => {
+			if tokio::fs::metadata(existing).await.is_ok() {
+				log!(2, "Skipping {}, page exists already", path.display());
+				return Ok(());
+			}
+		},
+	}
+	write_html(path, html, opt.compress_html).await
+}
+
+/// Read an HTML page written by `write_html`.
+async fn read_html(path: &Path, compressed: bool) -> Result<Vec<u8>> {
+	let data = tokio::fs::read(path).await?;
+	if !compressed {
+		return Ok(data);
+	}
+	let mut html = Vec::new();
+	GzipDecoder::new(&*data).read_to_end(&mut html).await?;
+	Ok(html)
+}
+
+/// Returns the `Last-Modified` time sent by the server.
+pub fn last_modified(resp: &reqwest::Response) -> Option<SystemTime> {
+	let date = resp.headers().get(reqwest::header::LAST_MODIFIED)?.to_str().ok()?;
+	OffsetDateTime::parse(date, &Rfc2822).ok().map(SystemTime::from)
+}
+
+/// Set the modification time of the file.
+pub async fn set_modified(path: &Path, modified: SystemTime) -> Result<()> {
+	let file = tokio::fs::OpenOptions::new()
+		.write(true)
+		.open(path)
+		.await
+		.context("failed to open file")?;
+	file.into_std()
+		.await
+		.set_modified(modified)
+		.context("failed to set modification time")?;
 	Ok(())
 }
 
-/// Create a directory. Does not error if the directory already exists.
+/// Create a directory and all missing parent directories.
+/// Does not error if the directory already exists (or is created by another task at the same time).
 pub async fn create_dir(path: &Path) -> Result<()> {
-	if let Err(e) = tokio::fs::create_dir(&path).await {
-		if e.kind() != tokio::io::ErrorKind::AlreadyExists {
-			return Err(e.into());
+	if DRY_RUN.load(Ordering::SeqCst) {
+		return Ok(());
+	}
+	let mut missing = Vec::new();
+	for dir in path.ancestors() {
+		if dir.as_os_str().is_empty() || tokio::fs::metadata(dir).await.is_ok() {
+			break;
 		}
+		missing.push(dir);
+	}
+	if missing.is_empty() {
+		return Ok(());
+	}
+	// (create_dir_all already ignores directories created concurrently)
+	tokio::fs::create_dir_all(path)
+		.await
+		.with_context(|| format!("failed to create directory {}", path.display()))?;
+	for dir in missing.into_iter().rev() {
+		set_permissions(dir, true).await?;
 	}
 	Ok(())
 }
 
+/// Apply the permissions specified using `--chmod`.
+/// Directories additionally get the execute bit wherever the file mode has the read bit.
+async fn set_permissions(path: &Path, is_dir: bool) -> Result<()> {
+	let mode = FILE_MODE.load(Ordering::SeqCst);
+	if mode == 0 {
+		return Ok(());
+	}
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+		let mode = if is_dir { mode | ((mode & 0o444) >> 2) } else { mode };
+		tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+			.await
+			.context("failed to set permissions")?;
+	}
+	#[cfg(not(unix))]
+	let _ = (path, is_dir);
+	Ok(())
+}
+
 #[cfg(not(target_os = "windows"))]
 const INVALID: &[char] = &['/', '\\'];
 #[cfg(target_os = "windows")]
 const INVALID: &[char] = &['/', '\\', ':', '<', '>', '"', '|', '?', '*'];
 
+/// Replaces characters that are not allowed in file names and
+/// shortens the name to at most `--max-name-length` bytes.
+/// Names are normalized to NFC, so they are identical on all platforms (macOS uses NFD).
 pub fn file_escape(s: &str) -> String {
-	s.replace(INVALID, "-")
+	let name = truncate_name(s.nfc().collect::<String>().replace(INVALID, "-"));
+	#[cfg(target_os = "windows")]
+	let name = windows_name(name);
+	name
+}
+
+/// Device names that can't be used as file names on Windows (with any extension)
+#[cfg(target_os = "windows")]
+const RESERVED_NAMES: &[&str] = &[
+	"CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2",
+	"LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Removes trailing dots and spaces (Windows drops them when creating the file)
+/// and appends an underscore to reserved device names.
+#[cfg(target_os = "windows")]
+fn windows_name(name: String) -> String {
+	let name = name.trim_end_matches(&['.', ' '][..]);
+	if name.is_empty() {
+		return "_".to_owned();
+	}
+	let (stem, extension) = name.split_at(name.find('.').unwrap_or(name.len()));
+	if RESERVED_NAMES.iter().any(|x| x.eq_ignore_ascii_case(stem.trim_end())) {
+		format!("{}_{}", stem, extension)
+	} else {
+		name.to_owned()
+	}
+}
+
+/// Truncates the name, keeping the extension and appending a short hash of the full name to keep it unique.
+fn truncate_name(name: String) -> String {
+	let max_len = MAX_NAME_LENGTH.load(Ordering::SeqCst);
+	if name.len() <= max_len {
+		return name;
+	}
+	let hash = format!("~{:x}", Sha256::digest(name.as_bytes()));
+	let hash = &hash[..9];
+	let extension = match name.rfind('.') {
+		Some(idx) if name.len() - idx <= 10 && !name[idx..].contains(' ') => &name[idx..],
+		_ => "",
+	};
+	let mut end = max_len.saturating_sub(hash.len() + extension.len());
+	while !name.is_char_boundary(end) {
+		end -= 1;
+	}
+	format!("{}{}{}", name[..end].trim_end(), hash, extension)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// the tests use the default --max-name-length of 200 bytes
+	const MAX: usize = 200;
+
+	#[test]
+	fn short_names_are_kept() {
+		assert_eq!(file_escape("Übungsblatt 1.pdf"), "Übungsblatt 1.pdf");
+		assert_eq!(file_escape("a/b\\c.pdf"), "a-b-c.pdf");
+	}
+
+	#[test]
+	fn truncate_multibyte_name() {
+		let name = format!("{}.pdf", "Ä€😀".repeat(40)); // 360 bytes
+		let escaped = file_escape(&name);
+		assert!(escaped.len() <= MAX, "{} bytes", escaped.len());
+		assert!(escaped.ends_with(".pdf"));
+		assert!(escaped.starts_with("Ä€😀"));
+		// a short hash of the full name is appended before the extension
+		let (_, hash) = escaped.trim_end_matches(".pdf").rsplit_once('~').unwrap();
+		assert_eq!(hash.len(), 8);
+		assert!(hash.chars().all(|x| x.is_ascii_hexdigit()));
+	}
+
+	#[test]
+	fn truncate_without_extension() {
+		let name = "ü".repeat(150);
+		let escaped = file_escape(&name);
+		assert!(escaped.len() <= MAX);
+		assert!(!escaped.contains('.'));
+		// names with spaces after the last dot don't have an extension
+		let name = format!("Vorlesung 1. {}", "Folien ".repeat(40));
+		let escaped = file_escape(&name);
+		assert!(escaped.len() <= MAX);
+		assert!(!escaped.ends_with(". "));
+	}
+
+	#[test]
+	fn truncated_names_are_unique() {
+		let prefix = "Zusammenfassung ".repeat(15);
+		let a = file_escape(&format!("{}Teil 1.pdf", prefix));
+		let b = file_escape(&format!("{}Teil 2.pdf", prefix));
+		assert_ne!(a, b);
+		assert_eq!(a.len(), b.len());
+		// the same name is always truncated the same way
+		assert_eq!(a, file_escape(&format!("{}Teil 1.pdf", prefix)));
+	}
+
+	#[test]
+	fn unicode_normalization() {
+		let nfc = "\u{dc}bung.pdf";
+		let nfd = "U\u{308}bung.pdf";
+		assert_ne!(nfc, nfd);
+		assert_eq!(file_escape(nfc), file_escape(nfd));
+		assert_eq!(file_escape(nfd), "Übung.pdf");
+	}
+
+	#[tokio::test]
+	async fn host_ticket_is_held_until_body_is_received() {
+		let semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(1));
+		let permit = std::sync::Arc::clone(&semaphore).try_acquire_owned().unwrap();
+		let mut resp = reqwest::Response::from(http::Response::new("video data"));
+		resp.extensions_mut().insert(queue::HostTicket::from(permit));
+
+		let mut body = body_stream(resp);
+		assert_eq!(semaphore.available_permits(), 0);
+		assert_eq!(body.next().await.unwrap().unwrap(), "video data");
+		assert!(body.next().await.is_none());
+		assert_eq!(semaphore.available_permits(), 0);
+		drop(body);
+		assert_eq!(semaphore.available_permits(), 1);
+	}
 }